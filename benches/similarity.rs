@@ -0,0 +1,89 @@
+//! Scoring a query against the embeddings of every chunk, the way search
+//! did before the embeddings were normalized in the cache and the way it
+//! does now. Run with `cargo bench --bench similarity`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rayon::prelude::*;
+
+// csep is a binary, so the kernels are compiled into the benchmark
+// directly, without the tests that use all of them
+#[allow(dead_code, unused_imports)]
+#[path = "../src/similarity.rs"]
+mod similarity;
+
+/// Dimensions of the default fastembed model
+const DIMENSIONS: usize = 384;
+
+/// How similarity was computed before, norms and all, with every sum over
+/// a single vector split across threads
+fn par_iter_cosine_similarity(v1: &[f32], v2: &[f32]) -> f32 {
+    let dot_product = v1.par_iter().zip(v2).map(|(a, b)| a * b).sum::<f32>();
+    let magnitude_v1 = (v1.par_iter().map(|a| a.powi(2)).sum::<f32>()).sqrt();
+    let magnitude_v2 = (v2.par_iter().map(|a| a.powi(2)).sum::<f32>()).sqrt();
+    dot_product / (magnitude_v1 * magnitude_v2)
+}
+
+/// Vectors that look random enough, the same on every run
+fn vectors(rows: usize) -> Vec<f32> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    (0..rows * DIMENSIONS)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 40) as f32 / (1u64 << 24) as f32 - 0.5
+        })
+        .collect()
+}
+
+fn pair(c: &mut Criterion) {
+    let vectors = vectors(2);
+    let (a, b) = vectors.split_at(DIMENSIONS);
+    let (normalized_a, normalized_b) = (similarity::normalized(a), similarity::normalized(b));
+
+    let mut group = c.benchmark_group("pair");
+    group.bench_function("par_iter cosine", |bencher| {
+        bencher.iter(|| par_iter_cosine_similarity(black_box(a), black_box(b)))
+    });
+    group.bench_function("cosine", |bencher| {
+        bencher.iter(|| similarity::cosine_similarity(black_box(a), black_box(b)))
+    });
+    group.bench_function("normalized dot", |bencher| {
+        bencher.iter(|| similarity::dot(black_box(&normalized_a), black_box(&normalized_b)))
+    });
+    group.finish();
+}
+
+fn scan(c: &mut Criterion) {
+    let mut group = c.benchmark_group("scan");
+    for rows in [1_000, 10_000, 100_000] {
+        let query = vectors(1);
+        let vectors = vectors(rows);
+        let mut normalized_vectors = vectors.clone();
+        normalized_vectors
+            .chunks_exact_mut(DIMENSIONS)
+            .for_each(similarity::normalize);
+        let normalized_query = similarity::normalized(&query);
+
+        // Files of ten chunks each scored in parallel, and every chunk with
+        // the parallel cosine, like search used to
+        group.bench_with_input(BenchmarkId::new("par_iter cosine", rows), &rows, |bencher, _| {
+            bencher.iter(|| {
+                vectors
+                    .par_chunks(10 * DIMENSIONS)
+                    .flat_map_iter(|file| {
+                        file.chunks_exact(DIMENSIONS)
+                            .map(|row| par_iter_cosine_similarity(&query, row))
+                    })
+                    .collect::<Vec<f32>>()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("normalized dot", rows), &rows, |bencher, _| {
+            bencher.iter(|| similarity::scores(black_box(&normalized_query), &normalized_vectors, DIMENSIONS))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, pair, scan);
+criterion_main!(benches);
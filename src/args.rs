@@ -1,4 +1,10 @@
-use clap::{command, Parser};
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use encoding_rs::Encoding;
+use serde::{Deserialize, Serialize};
+
+use crate::chunker::Sizer;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
@@ -7,16 +13,25 @@ pub struct Args {
     #[arg(index = 1)]
     pub query: Option<String>,
 
-    /// If provided will compare the query to this string and then return
-    /// the cosine similarity score
+    /// Files, directories or globs to search, defaults to the current
+    /// directory. If a single argument is given that does not resolve to
+    /// a path it is treated as a comparison string and the cosine
+    /// similarity score between it and the query is returned
     #[arg(index = 2)]
-    pub comparison: Option<String>,
+    pub paths: Vec<String>,
 
     /// Similarity floor, any result below this floating point will be
     /// filtered out from the results
     #[arg(short = 'f', long)]
     pub floor: Option<f32>,
 
+    /// Only print this many of the most similar results
+    #[arg(short = 'k', long)]
+    pub top: Option<usize>,
+
+    /// Only print this many of the most similar results from any one file
+    #[arg(long)]
+    pub max_per_file: Option<usize>,
 
     /// If set will not print out the query with the results
     #[arg(short, long)]
@@ -27,27 +42,235 @@ pub struct Args {
     pub list_models: bool,
 
 
-    /// Print in vimgrep compatible mode
+    /// Print in vimgrep compatible mode, same as --format vimgrep
     #[arg(short, long)]
     pub vimgrep: bool,
 
+    /// Set the output format, defaults to text
+    #[arg(long, value_enum)]
+    pub format: Option<OutputFormat>,
+
+    /// Combine the similarity of the embeddings with BM25 keyword scores,
+    /// so that exact identifiers and error codes are found too
+    #[arg(long)]
+    pub hybrid: bool,
+
+    /// How hybrid search combines the two scores
+    #[arg(long, value_enum, default_value_t = FusionMethod::Rrf)]
+    pub fusion: FusionMethod,
+
+    /// Weight of the embedding similarity in weighted fusion, between 0
+    /// and 1, the keyword score gets the rest
+    #[arg(long, default_value_t = 0.5)]
+    pub semantic_weight: f32,
+
+    /// Print the embedding similarity and keyword score of each hybrid
+    /// search result
+    #[arg(long)]
+    pub explain: bool,
+
+    /// Search big indexes with an approximate nearest neighbour graph,
+    /// which is much faster but may miss some of the best results. Without
+    /// --top at most --ef results are found in each index.
+    #[arg(long)]
+    pub ann: bool,
+
+    /// Candidates looked at by --ann searches, more find the best results
+    /// more reliably, defaults to 64
+    #[arg(long)]
+    pub ef: Option<usize>,
+
+    /// Links of every chunk in the --ann graph, more make a better and
+    /// bigger graph, defaults to 16
+    #[arg(long)]
+    pub hnsw_m: Option<usize>,
+
+    /// Candidates looked at when building the --ann graph, more make a
+    /// better graph that takes longer to build, defaults to 200
+    #[arg(long)]
+    pub ef_construction: Option<usize>,
+
+    /// Maximum size of a chunk, measured by --chunk-sizer, defaults to 100
+    #[arg(long)]
+    pub chunk_tokens: Option<usize>,
+
+    /// How much neighbouring chunks overlap, defaults to 0
+    #[arg(long)]
+    pub chunk_overlap: Option<usize>,
+
+    /// What the size of chunks is measured in, defaults to cl100k
+    #[arg(long, value_enum)]
+    pub chunk_sizer: Option<Sizer>,
+
+    /// Search only files that match this glob, or skip them if it starts
+    /// with !, can be given more than once
+    #[arg(short = 'g', long = "glob")]
+    pub globs: Vec<String>,
+
+    /// Search only files of this type, like rust or markdown, can be given
+    /// more than once
+    #[arg(short = 't', long = "type")]
+    pub types: Vec<String>,
+
+    /// Never search files of this type, can be given more than once
+    #[arg(short = 'T', long = "type-not")]
+    pub types_not: Vec<String>,
+
+    /// Search hidden files and directories
+    #[arg(long)]
+    pub hidden: bool,
+
+    /// Don't respect .gitignore, .ignore and .csepignore files
+    #[arg(long)]
+    pub no_ignore: bool,
+
+    /// Don't descend more than this many directories below each path
+    #[arg(long)]
+    pub max_depth: Option<usize>,
+
+    /// Skip files bigger than this, like 500K or 10M
+    #[arg(long, value_parser = parse_filesize)]
+    pub max_filesize: Option<u64>,
+
+    /// Follow symbolic links
+    #[arg(short = 'L', long)]
+    pub follow: bool,
+
+    /// Read files in this encoding, like latin1 or utf-16le, instead of
+    /// detecting it from their contents
+    #[arg(long, value_parser = parse_encoding)]
+    pub encoding: Option<&'static Encoding>,
+
+    /// Read files with an extension through a command that prints their
+    /// text, like doc=antiword, can be given more than once
+    #[arg(long = "pre", value_name = "EXT=COMMAND", value_parser = parse_preprocessor)]
+    pub preprocessors: Vec<(String, String)>,
+
     /// Set the model
     #[arg(short = 'M', long)]
     pub model: Option<String>,
 
-    /// Set whether to use the ollama or fastembed
+    /// Load a local ONNX model and its tokenizer files from this directory
+    /// with the fastembed client, instead of one of the models it
+    /// downloads
+    #[arg(long)]
+    pub model_path: Option<PathBuf>,
+
+    /// Never touch the network, fail if the model is not in the local
+    /// model cache. The ollama and openai clients may only use servers on
+    /// this machine.
+    #[arg(long)]
+    pub offline: bool,
+
+    /// Set whether to use the ollama, fastembed or openai client
     #[arg(short, long)]
     pub client: Option<String>,
 
+    /// Base URL of the Ollama server, defaults to OLLAMA_HOST, then the
+    /// config file, then http://localhost:11434
+    #[arg(long)]
+    pub ollama_url: Option<String>,
+
+    /// Base URL of an OpenAI compatible embeddings API, including the /v1
+    /// prefix, defaults to OPENAI_BASE_URL, then the config file, then
+    /// https://api.openai.com/v1
+    #[arg(long)]
+    pub openai_url: Option<String>,
+
     #[command(subcommand)]
     pub subcmd: Option<SubCommands>
 
 }
 
+/// Parse a size in bytes with an optional K, M or G suffix
+fn parse_filesize(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let (digits, multiplier) = match size.char_indices().last() {
+        Some((i, suffix)) if suffix.is_ascii_alphabetic() => {
+            let multiplier = match suffix.to_ascii_uppercase() {
+                'K' => 1 << 10,
+                'M' => 1 << 20,
+                'G' => 1 << 30,
+                _ => return Err(format!("unknown size suffix {}", suffix)),
+            };
+            (&size[..i], multiplier)
+        }
+        _ => (size, 1),
+    };
+    digits
+        .parse::<u64>()
+        .map(|digits| digits * multiplier)
+        .map_err(|_| format!("invalid size {}", size))
+}
+
+fn parse_encoding(label: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(label.as_bytes()).ok_or_else(|| format!("unknown encoding {}", label))
+}
+
+fn parse_preprocessor(preprocessor: &str) -> Result<(String, String), String> {
+    match preprocessor.split_once('=') {
+        Some((extension, command)) if !extension.is_empty() && !command.trim().is_empty() => {
+            Ok((extension.to_string(), command.to_string()))
+        }
+        _ => Err(format!("expected EXT=COMMAND, got {}", preprocessor)),
+    }
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Plain text, suitable for reading or passing to a language model
+    Text,
+    /// One file:line:column: location per result followed by the chunk
+    Vimgrep,
+    /// A single JSON document with all the results and a summary
+    Json,
+    /// One JSON object per result followed by a summary object
+    Jsonl,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FusionMethod {
+    /// Reciprocal rank fusion, only the ranks under each score matter
+    Rrf,
+    /// Weighted sum of the similarity and the keyword score scaled to the
+    /// best match, see --semantic-weight
+    Weighted,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatrixFormat {
+    /// The texts numbered, followed by the matrix with the numbers as
+    /// headers
+    Table,
+    /// The texts as the header row and first column of the matrix
+    Csv,
+    /// An object with the texts and the matrix as an array of rows
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClusterMethod {
+    /// Split the items into a fixed number of clusters around their means
+    Kmeans,
+    /// Merge the most similar clusters, starting from one per item, until
+    /// they are no longer similar enough or there are few enough of them
+    Agglomerative,
+}
+
 #[derive(Parser, Debug)]
 pub enum SubCommands {
     /// Options for managing the embeddings cache
     Cache(CacheSubcommand),
+    /// Inspect the configuration
+    Config(ConfigSubcommand),
+    /// Print the similarity of every pair of a list of texts
+    Compare(CompareSubcommand),
+    /// Group lines of text, or the chunks of files, that are about the
+    /// same thing
+    Cluster(ClusterSubcommand),
+    /// Find chunks that are near duplicates of each other
+    Dupes(DupesSubcommand),
 }
 
 #[derive(Parser, Debug)]
@@ -64,3 +287,105 @@ pub struct CacheSubcommand {
     pub build: bool
 }
 
+#[derive(Parser, Debug)]
+pub struct CompareSubcommand {
+    /// Texts to compare
+    pub texts: Vec<String>,
+
+    /// Also compare every non-empty line of this file, - reads them from
+    /// stdin
+    #[arg(long)]
+    pub file: Option<PathBuf>,
+
+    /// How to print the similarity matrix, defaults to table, or json if
+    /// the output format is json or jsonl
+    #[arg(long, value_enum)]
+    pub format: Option<MatrixFormat>,
+}
+
+#[derive(Parser, Debug)]
+pub struct ClusterSubcommand {
+    /// Files, directories or globs whose chunks are clustered, lines of
+    /// stdin are clustered if none are given
+    pub paths: Vec<String>,
+
+    /// Cluster every non-empty line of this file, - reads them from stdin
+    #[arg(long)]
+    pub file: Option<PathBuf>,
+
+    /// How the items are clustered, agglomerative for lines and k-means
+    /// for the chunks of files by default
+    #[arg(long, value_enum)]
+    pub method: Option<ClusterMethod>,
+
+    /// How many clusters to make. k-means defaults to the square root of
+    /// half the number of items
+    #[arg(long)]
+    pub clusters: Option<usize>,
+
+    /// Agglomerative clustering without --clusters stops merging when no
+    /// two clusters are on average this similar
+    #[arg(long, default_value_t = 0.5)]
+    pub min_similarity: f32,
+}
+
+#[derive(Parser, Debug)]
+pub struct DupesSubcommand {
+    /// Files, directories or globs to look for duplicates in, defaults to
+    /// the current directory
+    pub paths: Vec<String>,
+
+    /// Chunks at least this similar are duplicates
+    #[arg(long, default_value_t = 0.95)]
+    pub threshold: f32,
+
+    /// Chunks of the same file are only duplicates when there are at least
+    /// this many lines between them
+    #[arg(long, default_value_t = 20)]
+    pub min_distance: usize,
+}
+
+#[derive(Parser, Debug)]
+pub struct ConfigSubcommand {
+    #[command(subcommand)]
+    pub action: ConfigAction,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigAction {
+    /// Print the effective settings and where each one came from, the
+    /// command line, the project's .csep.toml, the user's config file or
+    /// the defaults
+    Show,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_filesize() {
+        assert_eq!(parse_filesize("512"), Ok(512));
+        assert_eq!(parse_filesize("500K"), Ok(500 * 1024));
+        assert_eq!(parse_filesize("10m"), Ok(10 * 1024 * 1024));
+        assert!(parse_filesize("10X").is_err());
+        assert!(parse_filesize("M").is_err());
+    }
+
+    #[test]
+    fn test_parse_encoding() {
+        assert_eq!(parse_encoding("latin1").unwrap().name(), "windows-1252");
+        assert_eq!(parse_encoding("UTF-16LE").unwrap().name(), "UTF-16LE");
+        assert!(parse_encoding("klingon").is_err());
+    }
+
+    #[test]
+    fn test_parse_preprocessor() {
+        assert_eq!(
+            parse_preprocessor("odt=pandoc --to plain").unwrap(),
+            ("odt".to_string(), "pandoc --to plain".to_string())
+        );
+        assert!(parse_preprocessor("antiword").is_err());
+        assert!(parse_preprocessor("doc=").is_err());
+    }
+}
@@ -1,103 +1,611 @@
-use std::{fs, path::PathBuf};
-
 use anyhow::Result;
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
-use text_splitter::{ChunkConfig, TextSplitter};
-use tiktoken_rs::cl100k_base;
+use std::{collections::HashMap, ops::Range, path::Path};
+
+use text_splitter::{
+    Characters, ChunkConfig, ChunkSizer, CodeSplitter, MarkdownSplitter, TextSplitter,
+};
+use tiktoken_rs::{cl100k_base, CoreBPE};
 use tracing::warn;
 
 use crate::{
-    clients::{EmbeddingsClient, EmbeddingsClientImpl},
-    files::read_file_with_fallback,
+    clients::EmbeddingsClient,
+    config::ChunkingConfig,
+    extract::PAGE_BREAK,
+    index::CacheKey,
+    markdown,
+    syntax::{self, Node},
 };
 
-#[derive(Serialize, Deserialize)]
-pub struct Chunk {
-    pub line: usize,
-    pub text: String,
-    pub embeddings: Vec<f32>,
+/// Bump this whenever the way files are split into chunks changes, so
+/// that caches built by older versions are never served
+pub const CHUNKER_VERSION: u32 = 7;
+
+/// Maximum size of a single chunk when nothing else is configured
+pub const DEFAULT_CHUNK_TOKENS: usize = 100;
+
+/// What the size of a chunk is measured in
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Sizer {
+    /// Unicode characters
+    Characters,
+    /// Tokens of the cl100k tokenizer used by OpenAI models
+    Cl100k,
+    /// Tokens of the embedding model's own tokenizer, only known for
+    /// fastembed models
+    Model,
 }
 
-pub fn get_cache_path() -> PathBuf {
-    let tmp_dir = dirs::cache_dir().unwrap();
-    tmp_dir.join("csep").join("embeddings")
+/// How the chunks of a file are sized. These are stored with every file in
+/// the index, so that changing them only embeds the files they apply to
+/// again.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkSettings {
+    /// Maximum size of a chunk
+    pub tokens: usize,
+    /// How much neighbouring chunks may overlap
+    pub overlap: usize,
+    pub sizer: Sizer,
 }
 
-pub fn count_lines_in_text(text: &str) -> usize {
-    text.lines().count()
+/// Chunk settings for every file, from the command line, the config file
+/// and the defaults, in that order of precedence
+#[derive(Clone, Debug)]
+pub struct Chunking {
+    default: ChunkSettings,
+    /// Settings for files with a given extension
+    extensions: HashMap<String, ChunkSettings>,
 }
 
-/// Chunk a file into smaller pieces and get embeddings for each chunk
-/// using TextSplitter and the provided embeddings client
-pub async fn get_chunks_and_embeddings_or_load_from_cache<'a>(
-    file: &'a str,
-    embeddings_client: &EmbeddingsClientImpl,
-) -> Result<(String, Vec<Chunk>)> {
-    let file_text = match read_file_with_fallback(file) {
-        Ok(text) => text,
-        Err(_err) => {
-            warn!("Error reading file {}", file);
-            return Ok((file.to_string(), Vec::new()));
+impl Chunking {
+    pub fn new(
+        config: &ChunkingConfig,
+        tokens: Option<usize>,
+        overlap: Option<usize>,
+        sizer: Option<Sizer>,
+    ) -> Result<Self> {
+        let resolve =
+            |file_tokens: Option<usize>, file_overlap: Option<usize>, file_sizer: Option<Sizer>| {
+                let settings = ChunkSettings {
+                    tokens: tokens
+                        .or(file_tokens)
+                        .or(config.tokens)
+                        .unwrap_or(DEFAULT_CHUNK_TOKENS),
+                    overlap: overlap.or(file_overlap).or(config.overlap).unwrap_or(0),
+                    sizer: sizer
+                        .or(file_sizer)
+                        .or(config.sizer)
+                        .unwrap_or(Sizer::Cl100k),
+                };
+                if settings.tokens == 0 {
+                    return Err(anyhow::anyhow!("Chunk size must be at least 1"));
+                }
+                if settings.overlap >= settings.tokens {
+                    return Err(anyhow::anyhow!(
+                        "Chunk overlap must be smaller than the chunk size, got {} and {}",
+                        settings.overlap,
+                        settings.tokens
+                    ));
+                }
+                Ok(settings)
+            };
+
+        let mut extensions = HashMap::new();
+        for (extension, file_config) in &config.extensions {
+            let settings = resolve(file_config.tokens, file_config.overlap, file_config.sizer)?;
+            extensions.insert(extension.trim_start_matches('.').to_lowercase(), settings);
         }
-    };
 
-    let hash_of_file = Sha256::digest(file_text.as_bytes());
-    let cache_file_name = format!("{:x}.cache", hash_of_file);
-    let file_path = get_cache_path().join(cache_file_name);
+        Ok(Chunking {
+            default: resolve(None, None, None)?,
+            extensions,
+        })
+    }
 
-    if file_path.exists() {
-        match bincode::deserialize(&fs::read(&file_path)?) {
-            Ok(chunks) => {
-                return Ok((file.to_string(), chunks));
-            }
-            Err(err) => {
-                warn!("Error deserializing cache file {}: {}", file, err);
-                // Delete the file, if we cant read from it, its probably corrupt
-                match fs::remove_file(&file_path) {
-                    Ok(_) => (),
-                    Err(err) => warn!("Error removing cache file {}: {}", file, err),
-                }
+    /// Settings for a file
+    pub fn for_path(&self, path: &str) -> ChunkSettings {
+        Path::new(path)
+            .extension()
+            .and_then(|extension| {
+                self.extensions
+                    .get(&extension.to_string_lossy().to_lowercase())
+            })
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    /// Use cl100k tokens wherever the model's own tokenizer was asked for
+    /// but the client does not have one
+    pub fn without_model_sizer(&self) -> Self {
+        let replace = |mut settings: ChunkSettings| {
+            if settings.sizer == Sizer::Model {
+                settings.sizer = Sizer::Cl100k;
             }
+            settings
         };
+        Chunking {
+            default: replace(self.default),
+            extensions: self
+                .extensions
+                .iter()
+                .map(|(extension, settings)| (extension.clone(), replace(*settings)))
+                .collect(),
+        }
+    }
+
+    pub fn uses_model_sizer(&self) -> bool {
+        std::iter::once(&self.default)
+            .chain(self.extensions.values())
+            .any(|settings| settings.sizer == Sizer::Model)
+    }
+
+    /// Settings that can be used with a client, see without_model_sizer
+    pub fn for_client<C: EmbeddingsClient>(&self, embeddings_client: &C) -> Self {
+        if self.uses_model_sizer() && embeddings_client.tokens("").is_none() {
+            warn!(
+                "The {} client does not expose the tokenizer of its model, sizing chunks in \
+                 cl100k tokens instead",
+                embeddings_client.name()
+            );
+            return self.without_model_sizer();
+        }
+        self.clone()
     }
+}
+
+/// Measures chunks in the unit the settings ask for
+struct Counter<'a, C> {
+    sizer: Sizer,
+    cl100k: &'a CoreBPE,
+    embeddings_client: &'a C,
+}
+
+impl<C: EmbeddingsClient> ChunkSizer for &Counter<'_, C> {
+    fn size(&self, chunk: &str) -> usize {
+        match self.sizer {
+            Sizer::Characters => Characters.size(chunk),
+            Sizer::Cl100k => self.cl100k.encode_ordinary(chunk).len(),
+            Sizer::Model => self
+                .embeddings_client
+                .tokens(chunk)
+                .unwrap_or_else(|| self.cl100k.encode_ordinary(chunk).len()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Chunk {
+    /// Line of the first character of the chunk, starting at 1
+    pub start_line: usize,
+    /// Byte column of the first character of the chunk, starting at 1
+    pub start_column: usize,
+    /// Line of the last character of the chunk, starting at 1
+    pub end_line: usize,
+    /// Byte column of the last character of the chunk, starting at 1
+    pub end_column: usize,
+    /// Byte offset of the start of the chunk in the file
+    pub start: usize,
+    /// Byte offset of the end of the chunk in the file, exclusive
+    pub end: usize,
+    /// Definition the chunk is in, like `impl Index > fn update`, or the
+    /// headings above it in Markdown, like `Installation > Ollama client
+    /// option`
+    pub symbol: Option<String>,
+    /// Page, slide or sheet the chunk is on, starting at 1, for documents
+    /// whose text was extracted
+    pub page: Option<usize>,
+    pub text: String,
+}
+
+/// A piece of a file that will become a chunk
+struct Piece {
+    range: Range<usize>,
+    symbol: Option<String>,
+    page: Option<usize>,
+}
+
+/// Append a definition to the breadcrumb of the definitions around it
+fn breadcrumb(context: Option<&str>, label: &str) -> String {
+    match context {
+        Some(context) => format!("{} > {}", context, label),
+        None => label.to_string(),
+    }
+}
+
+/// What a piece of a file is in, like `impl Index > fn update`. A piece in
+/// a single definition is in that definition, or in the definition nested
+/// in it that the piece is in. A piece with several definitions is in
+/// whatever they are in.
+fn symbol(nodes: &[Node], range: &Range<usize>, context: Option<String>) -> Option<String> {
+    let mut overlapping = nodes
+        .iter()
+        .filter(|node| node.range.start < range.end && range.start < node.range.end);
+    match (overlapping.next(), overlapping.next()) {
+        (Some(node), None) => {
+            let label = breadcrumb(context.as_deref(), &node.label);
+            if node.range.start <= range.start && range.end <= node.range.end {
+                symbol(&node.children, range, Some(label))
+            } else {
+                Some(label)
+            }
+        }
+        _ => context,
+    }
+}
+
+/// A chunk of a source or Markdown file, in whatever definition or section
+/// of the nodes it is in
+fn piece(nodes: &[Node], offset: usize, chunk: &str) -> Piece {
+    let range = offset..offset + chunk.len();
+    Piece {
+        symbol: symbol(nodes, &range, None),
+        range,
+        page: None,
+    }
+}
+
+/// Split a file into pieces no bigger than the settings allow, along its
+/// sections if it is Markdown or its syntax tree if it is a source file in
+/// a known language. Paged documents are split page by page, so that no
+/// piece spans two pages.
+fn split<C: EmbeddingsClient>(
+    path: &str,
+    text: &str,
+    settings: &ChunkSettings,
+    paged: bool,
+    embeddings_client: &C,
+) -> Result<Vec<Piece>> {
+    let cl100k = cl100k_base()?;
+    let counter = Counter {
+        sizer: settings.sizer,
+        cl100k: &cl100k,
+        embeddings_client,
+    };
+    let config = || -> Result<_> {
+        Ok(ChunkConfig::new(settings.tokens)
+            .with_overlap(settings.overlap)?
+            .with_sizer(&counter))
+    };
+    let splitter = TextSplitter::new(config()?);
+
+    if paged {
+        let mut pieces = Vec::new();
+        let mut start = 0;
+        for (i, page) in text.split(PAGE_BREAK).enumerate() {
+            pieces.extend(splitter.chunk_indices(page).map(|(offset, chunk)| Piece {
+                range: start + offset..start + offset + chunk.len(),
+                symbol: None,
+                page: Some(i + 1),
+            }));
+            start += page.len() + PAGE_BREAK.len_utf8();
+        }
+        return Ok(pieces);
+    }
+
+    // Chunks named after the definitions or sections they are in
+    let pieces: Vec<Piece> = if let Some(language) = syntax::language(path) {
+        let nodes = syntax::parse(path, text).unwrap_or_default();
+        CodeSplitter::new(language, config()?)?
+            .chunk_indices(text)
+            .map(|(offset, chunk)| piece(&nodes, offset, chunk))
+            .collect()
+    } else if let Some(nodes) = markdown::parse(path, text) {
+        MarkdownSplitter::new(config()?)
+            .chunk_indices(text)
+            .map(|(offset, chunk)| piece(&nodes, offset, chunk))
+            .collect()
+    } else {
+        return Ok(splitter
+            .chunk_indices(text)
+            .map(|(offset, chunk)| Piece {
+                range: offset..offset + chunk.len(),
+                symbol: None,
+                page: None,
+            })
+            .collect());
+    };
+
+    // The splitters leave out the whitespace around chunks, but not pieces
+    // that are only closing brackets
+    Ok(pieces
+        .into_iter()
+        .filter(|piece| text[piece.range.clone()].chars().any(char::is_alphanumeric))
+        .collect())
+}
+
+/// Byte offsets of the start of every line in a text
+fn line_starts(text: &str) -> Vec<usize> {
+    std::iter::once(0)
+        .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+        .collect()
+}
+
+/// Line and byte column, both starting at 1, of a byte offset in a text
+/// given the offsets of the start of its lines
+fn position(line_starts: &[usize], offset: usize) -> (usize, usize) {
+    let line = line_starts.partition_point(|&start| start <= offset);
+    (line, offset - line_starts[line - 1] + 1)
+}
 
-    let tokenizer = cl100k_base()?;
-    let max_tokens = 100;
-    let splitter = TextSplitter::new(ChunkConfig::new(max_tokens).with_sizer(tokenizer));
+/// Chunk the text of a file into smaller pieces and get embeddings for
+/// each chunk using the provided embeddings client. Source files are split
+/// with CodeSplitter, Markdown with MarkdownSplitter and anything else with
+/// TextSplitter. Markdown chunks are embedded along with the headings
+/// above them, which say a lot about what a chunk is about. The text of
+/// paged documents has its pages separated by form feeds.
+pub async fn get_chunks_and_embeddings<C: EmbeddingsClient + Sync>(
+    path: &str,
+    file_text: &str,
+    embeddings_client: &C,
+    cache_key: &CacheKey,
+    settings: &ChunkSettings,
+    paged: bool,
+) -> Result<(Vec<Chunk>, Vec<Vec<f32>>)> {
+    let pieces = split(path, file_text, settings, paged, embeddings_client)?;
+    let str_chunks: Vec<&str> = pieces
+        .iter()
+        .map(|piece| &file_text[piece.range.clone()])
+        .collect();
+    if str_chunks.is_empty() {
+        return Ok((Vec::new(), Vec::new()));
+    }
 
-    let str_chunks: Vec<&str> = splitter.chunks(&file_text).collect();
-    let embeddings_batch = embeddings_client.get_embeddings(&str_chunks[..]).await?;
+    let embedding_texts: Vec<String> = pieces
+        .iter()
+        .zip(&str_chunks)
+        .map(|(piece, chunk)| match &piece.symbol {
+            Some(headings) if markdown::is_markdown(path) => format!("{}\n\n{}", headings, chunk),
+            _ => chunk.to_string(),
+        })
+        .collect();
+    let embedding_texts: Vec<&str> = embedding_texts.iter().map(String::as_str).collect();
+    let embeddings_batch = embeddings_client.get_embeddings(&embedding_texts).await?;
 
-    let mut lc = 1;
-    let chunks = str_chunks
+    if let Some(embeddings) = embeddings_batch
         .iter()
-        .zip(embeddings_batch.iter())
-        .enumerate()
-        .map(|(_i, (chunk, embeddings))| {
-            lc += count_lines_in_text(chunk);
+        .find(|embeddings| embeddings.len() != cache_key.dimensions)
+    {
+        return Err(anyhow::anyhow!(
+            "Model {} returned {} dimensional embeddings, expected {}",
+            cache_key.model,
+            embeddings.len(),
+            cache_key.dimensions
+        ));
+    }
+
+    let line_starts = line_starts(file_text);
+    let chunks = pieces
+        .into_iter()
+        .zip(str_chunks)
+        .map(|(piece, chunk)| {
+            let offset = piece.range.start;
+            let end = piece.range.end;
+            let (start_line, start_column) = position(&line_starts, offset);
+            // Chunks are never empty, so the last character starts before
+            // the end of the chunk
+            let last_char = chunk.char_indices().last().map_or(0, |(i, _)| i);
+            let (end_line, end_column) = position(&line_starts, offset + last_char);
             Chunk {
-                line: lc,
+                start_line,
+                start_column,
+                end_line,
+                end_column,
+                start: offset,
+                end,
+                symbol: piece.symbol,
+                page: piece.page,
                 text: chunk.to_string(),
-                embeddings: embeddings.to_owned(),
             }
         })
         .collect();
 
-    fs::create_dir_all(get_cache_path())?;
-    fs::write(file_path, bincode::serialize(&chunks)?)?;
-
-    Ok((file.to_string(), chunks))
+    Ok((chunks, embeddings_batch))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        clients::fake::FakeEmbeddingsClient,
+        config::{ChunkingConfig, ChunkingOverride},
+        index::tests::cache_key,
+    };
+
+    fn settings(tokens: usize) -> ChunkSettings {
+        ChunkSettings {
+            tokens,
+            overlap: 0,
+            sizer: Sizer::Cl100k,
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_chunk_positions() {
+        let first = "alpha ".repeat(60);
+        let second = "beta ".repeat(60);
+        let text = format!("{}\n\n  {}\n", first.trim_end(), second.trim_end());
+
+        let client = FakeEmbeddingsClient::new();
+        let key = cache_key();
+        let (chunks, embeddings) =
+            get_chunks_and_embeddings("notes.txt", &text, &client, &key, &settings(100), false)
+                .await
+                .unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(embeddings.len(), 2);
+
+        for chunk in &chunks {
+            assert_eq!(&text[chunk.start..chunk.end], chunk.text);
+        }
+
+        let first_chunk = &chunks[0];
+        assert_eq!((first_chunk.start_line, first_chunk.start_column), (1, 1));
+        assert_eq!((first_chunk.end_line, first_chunk.end_column), (1, 359));
+
+        // The blank line and indentation between the chunks are trimmed
+        let second_chunk = &chunks[1];
+        assert_eq!((second_chunk.start_line, second_chunk.start_column), (3, 3));
+        assert_eq!((second_chunk.end_line, second_chunk.end_column), (3, 301));
+    }
+
+    #[tokio::test]
+    pub async fn test_code_chunks() {
+        let body = "    let total = values.iter().sum::<f32>();\n".repeat(20);
+        let text = format!(
+            "use std::fmt;\n\nfn small() -> u32 {{\n    1\n}}\n\nimpl Stats {{\n    \
+             fn mean(&self) -> f32 {{\n{}    }}\n\n    \
+             fn len(&self) -> usize {{\n        self.values.len()\n    }}\n}}\n",
+            body
+        );
+
+        let client = FakeEmbeddingsClient::new();
+        let key = cache_key();
+        let (chunks, _) =
+            get_chunks_and_embeddings("src/stats.rs", &text, &client, &key, &settings(100), false)
+                .await
+                .unwrap();
+
+        for chunk in &chunks {
+            assert_eq!(&text[chunk.start..chunk.end], chunk.text);
+        }
+        let symbols: Vec<Option<&str>> = chunks.iter().map(|c| c.symbol.as_deref()).collect();
+        assert_eq!(symbols.first(), Some(&Some("fn small")));
+        assert!(chunks[0].text.starts_with("use std::fmt;"));
+        assert!(chunks[0].text.ends_with("    1\n}"));
+
+        // The impl is too big for one chunk, so it is split further down
+        // its syntax tree, and the pieces of the long method are in it
+        assert!(symbols[1..]
+            .iter()
+            .all(|symbol| symbol.is_some_and(|symbol| symbol.starts_with("impl Stats"))));
+        let in_mean = symbols.iter().filter(|symbol| **symbol == Some("impl Stats > fn mean"));
+        assert!(in_mean.count() > 1);
+        // The end of one method along with the next is in the impl block
+        let last = chunks.last().unwrap();
+        assert!(last.text.ends_with("self.values.len()\n    }\n}"));
+        assert_eq!(last.symbol.as_deref(), Some("impl Stats"));
+    }
+
+    #[tokio::test]
+    pub async fn test_markdown_chunks() {
+        let text = "# csep\n\ncsep prints the chunks of files that are similar to a query, like grep does for regular expressions.\n\n## Installation\n\nInstall it with cargo install csep, or build it from source.\n\n### Ollama client option\nPull a model first.\n";
+
+        let client = FakeEmbeddingsClient::new();
+        let key = cache_key();
+        let (chunks, embeddings) =
+            get_chunks_and_embeddings("README.md", text, &client, &key, &settings(20), false)
+                .await
+                .unwrap();
+
+        let last = chunks.last().unwrap();
+        assert_eq!(last.text, "### Ollama client option\nPull a model first.");
+        assert_eq!(
+            last.symbol.as_deref(),
+            Some("csep > Installation > Ollama client option")
+        );
+
+        // The headings are embedded along with the chunk, the fake client
+        // counts the letters a, e and o
+        let embedded = format!("{}\n\n{}", last.symbol.as_ref().unwrap(), last.text);
+        let count = |c| embedded.matches(c).count() as f32;
+        assert_eq!(
+            embeddings.last().unwrap(),
+            &vec![count('a'), count('e'), count('o')]
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_paged_chunks() {
+        let text = format!(
+            "First page\n{}Second page{}{}Fourth",
+            PAGE_BREAK, PAGE_BREAK, PAGE_BREAK
+        );
+
+        let client = FakeEmbeddingsClient::new();
+        let key = cache_key();
+        let (chunks, _) =
+            get_chunks_and_embeddings("deck.pdf", &text, &client, &key, &settings(100), true)
+                .await
+                .unwrap();
+
+        // Empty pages still count
+        let pages: Vec<(Option<usize>, &str)> = chunks
+            .iter()
+            .map(|chunk| (chunk.page, chunk.text.as_str()))
+            .collect();
+        assert_eq!(
+            pages,
+            vec![
+                (Some(1), "First page"),
+                (Some(2), "Second page"),
+                (Some(4), "Fourth")
+            ]
+        );
+        for chunk in &chunks {
+            assert_eq!(&text[chunk.start..chunk.end], chunk.text);
+        }
+    }
 
     #[test]
-    pub fn test_count_lines_in_text() {
-        let text = "Hello\nWorld\n";
-        assert_eq!(count_lines_in_text(text), 2);
+    pub fn test_position() {
+        let text = "Hello\nWorld\n\n  indented";
+        let starts = line_starts(text);
+        assert_eq!(starts, vec![0, 6, 12, 13]);
+
+        assert_eq!(position(&starts, 0), (1, 1));
+        assert_eq!(position(&starts, 4), (1, 5));
+        assert_eq!(position(&starts, 5), (1, 6));
+        assert_eq!(position(&starts, 6), (2, 1));
+        assert_eq!(position(&starts, 12), (3, 1));
+        assert_eq!(position(&starts, 15), (4, 3));
     }
-}
 
+    #[test]
+    pub fn test_chunking_precedence() {
+        let mut config = ChunkingConfig {
+            tokens: Some(200),
+            overlap: Some(20),
+            ..Default::default()
+        };
+        config.extensions.insert(
+            ".MD".to_string(),
+            ChunkingOverride {
+                tokens: Some(400),
+                sizer: Some(Sizer::Model),
+                ..Default::default()
+            },
+        );
+
+        let chunking = Chunking::new(&config, None, None, None).unwrap();
+        assert_eq!(
+            chunking.for_path("src/main.rs"),
+            ChunkSettings {
+                tokens: 200,
+                overlap: 20,
+                sizer: Sizer::Cl100k
+            }
+        );
+        assert_eq!(
+            chunking.for_path("docs/README.md"),
+            ChunkSettings {
+                tokens: 400,
+                overlap: 20,
+                sizer: Sizer::Model
+            }
+        );
+
+        // The command line wins over the config file
+        let chunking = Chunking::new(&config, Some(50), None, None).unwrap();
+        assert_eq!(chunking.for_path("README.md").tokens, 50);
+
+        // The fake client has no tokenizer
+        let chunking = chunking.for_client(&FakeEmbeddingsClient::new());
+        assert_eq!(chunking.for_path("README.md").sizer, Sizer::Cl100k);
+
+        assert!(Chunking::new(&config, Some(20), None, None).is_err());
+        let error = Chunking::new(&ChunkingConfig::default(), Some(0), None, None).unwrap_err();
+        assert_eq!(error.to_string(), "Chunk size must be at least 1");
+    }
+}
@@ -0,0 +1,44 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use super::EmbeddingsClient;
+
+/// Embeds text as the counts of a few letters, and counts how many texts
+/// it has been asked to embed
+pub struct FakeEmbeddingsClient {
+    pub embedded: AtomicUsize,
+}
+
+impl FakeEmbeddingsClient {
+    pub fn new() -> Self {
+        FakeEmbeddingsClient {
+            embedded: AtomicUsize::new(0),
+        }
+    }
+}
+
+#[async_trait]
+impl EmbeddingsClient for FakeEmbeddingsClient {
+    async fn get_embeddings(&self, text: &[&str]) -> Result<Vec<Vec<f32>>> {
+        self.embedded.fetch_add(text.len(), Ordering::SeqCst);
+        Ok(text
+            .iter()
+            .map(|t| {
+                ['a', 'e', 'o']
+                    .iter()
+                    .map(|c| t.matches(*c).count() as f32)
+                    .collect()
+            })
+            .collect())
+    }
+
+    fn name(&self) -> &str {
+        "fake"
+    }
+
+    fn model(&self) -> &str {
+        "letters"
+    }
+}
@@ -1,15 +1,37 @@
-use std::path::PathBuf;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
 
-use fastembed::{EmbeddingModel, InitOptions, TextEmbedding};
+use fastembed::{
+    EmbeddingModel, InitOptions, InitOptionsUserDefined, ModelInfo, Pooling, TextEmbedding,
+    TokenizerFiles, UserDefinedEmbeddingModel,
+};
 
 use rayon::prelude::*;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use async_trait::async_trait;
+use sha2::{Digest, Sha256};
 
-use super::EmbeddingsClient;
+use crate::config::FastEmbedConfig;
+
+use super::{probed_dimensions, EmbeddingsClient};
+
+pub const DEFAULT_FASTEMBED_MODEL: EmbeddingModel = EmbeddingModel::AllMiniLML6V2;
+
+/// Files every model needs besides the ONNX model itself
+const TOKENIZER_FILES: [&str; 4] = [
+    "tokenizer.json",
+    "config.json",
+    "special_tokens_map.json",
+    "tokenizer_config.json",
+];
 
 pub struct FastEmbeddingsClient {
-    model: TextEmbedding
+    model: TextEmbedding,
+    name: String,
+    dimensions: usize,
 }
 
 pub fn get_cache_path() -> PathBuf {
@@ -17,16 +39,201 @@ pub fn get_cache_path() -> PathBuf {
     tmp_dir.join("csep").join("models")
 }
 
+/// Name used to select a fastembed model with --model. This is the
+/// HuggingFace repository of the model, with -Q appended for quantized
+/// variants that share a repository with the full precision model.
+pub fn model_name(info: &ModelInfo<EmbeddingModel>) -> String {
+    let quantized = format!("{:?}", info.model).ends_with('Q');
+    if quantized && !info.model_code.ends_with("-Q") {
+        format!("{}-Q", info.model_code)
+    } else {
+        info.model_code.clone()
+    }
+}
+
+/// Look up a fastembed model by its full name or by its name without the
+/// organisation, ignoring case
+pub fn find_model(name: &str) -> Result<ModelInfo<EmbeddingModel>> {
+    let models = TextEmbedding::list_supported_models();
+    let name = name.to_lowercase();
+
+    let found = models.iter().find(|info| {
+        let full_name = model_name(info).to_lowercase();
+        let short_name = full_name.rsplit('/').next().unwrap_or_default();
+        full_name == name || short_name == name
+    });
+    if let Some(info) = found {
+        return Ok(info.clone());
+    }
+
+    let suggestions: Vec<String> = models
+        .iter()
+        .map(model_name)
+        .filter(|model| model.to_lowercase().contains(&name))
+        .collect();
+    let mut message = format!("Unknown fastembed model {}", name);
+    if !suggestions.is_empty() {
+        message.push_str(&format!(", did you mean one of {}?", suggestions.join(", ")));
+    }
+    message.push_str("\nRun csep --list-models --client fastembed to see the available models");
+    Err(anyhow::anyhow!(message))
+}
+
+/// Files of a model that are not in the local model cache. This follows
+/// the HuggingFace cache layout, a model with no missing files is loaded by
+/// fastembed without making any requests.
+fn missing_files(cache_path: &Path, info: &ModelInfo<EmbeddingModel>) -> Vec<String> {
+    let repo_dir = cache_path.join(format!("models--{}", info.model_code.replace('/', "--")));
+    let snapshot = fs::read_to_string(repo_dir.join("refs").join("main"))
+        .ok()
+        .map(|commit| repo_dir.join("snapshots").join(commit.trim()));
+
+    std::iter::once(&info.model_file)
+        .chain(info.additional_files.iter())
+        .map(|file| file.as_str())
+        .chain(TOKENIZER_FILES)
+        .filter(|file| {
+            snapshot
+                .as_ref()
+                .is_none_or(|snapshot| !snapshot.join(file).exists())
+        })
+        .map(|file| file.to_string())
+        .collect()
+}
+
+/// Whether the files of a model are already in the local model cache
+pub fn is_downloaded(info: &ModelInfo<EmbeddingModel>) -> bool {
+    missing_files(&get_cache_path(), info).is_empty()
+}
+
+/// Read the ONNX model and tokenizer files from a directory. The model may
+/// be at model.onnx, onnx/model.onnx or be the only .onnx file there.
+fn read_local_model(dir: &Path) -> Result<(PathBuf, Vec<u8>, TokenizerFiles)> {
+    let read = |file: &str| {
+        fs::read(dir.join(file))
+            .with_context(|| format!("Could not read {} in local model {}", file, dir.display()))
+    };
+
+    let mut onnx_file = ["model.onnx", "onnx/model.onnx"]
+        .iter()
+        .map(|file| dir.join(file))
+        .find(|path| path.exists());
+    if onnx_file.is_none() {
+        let onnx_files: Vec<PathBuf> = fs::read_dir(dir)
+            .with_context(|| format!("Could not read local model {}", dir.display()))?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "onnx"))
+            .collect();
+        if let [single] = onnx_files.as_slice() {
+            onnx_file = Some(single.clone());
+        }
+    }
+    let onnx_file = onnx_file.ok_or(anyhow::anyhow!(
+        "Could not find the ONNX model in {}, expected model.onnx",
+        dir.display()
+    ))?;
+
+    let tokenizer_files = TokenizerFiles {
+        tokenizer_file: read(TOKENIZER_FILES[0])?,
+        config_file: read(TOKENIZER_FILES[1])?,
+        special_tokens_map_file: read(TOKENIZER_FILES[2])?,
+        tokenizer_config_file: read(TOKENIZER_FILES[3])?,
+    };
+    let onnx_bytes = fs::read(&onnx_file)
+        .with_context(|| format!("Could not read {}", onnx_file.display()))?;
+
+    Ok((onnx_file, onnx_bytes, tokenizer_files))
+}
+
+/// Name of a local model, the directory it is in along with a hash of the
+/// size and modification time of the ONNX file, so that embeddings cached
+/// for an older version of the model are not reused
+fn local_model_name(dir: &Path, onnx_file: &Path) -> Result<String> {
+    let metadata = fs::metadata(onnx_file)?;
+    let mtime = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or_default();
+
+    let mut hasher = Sha256::new();
+    hasher.update(metadata.len().to_le_bytes());
+    hasher.update(mtime.to_le_bytes());
+    let hash = format!("{:x}", hasher.finalize());
+
+    Ok(format!("{}@{}", fs::canonicalize(dir)?.display(), &hash[..12]))
+}
+
+fn parse_pooling(pooling: &Option<String>) -> Result<Pooling> {
+    match pooling.as_deref() {
+        None | Some("mean") => Ok(Pooling::Mean),
+        Some("cls") => Ok(Pooling::Cls),
+        Some(other) => Err(anyhow::anyhow!(
+            "Unknown pooling {}, expected mean or cls",
+            other
+        )),
+    }
+}
+
 impl FastEmbeddingsClient {
-    pub fn new() -> Self {
-        
-        let init_options = InitOptions::new(EmbeddingModel::AllMiniLML6V2).with_show_download_progress(true).with_cache_dir(get_cache_path());
-        let model = TextEmbedding::try_new(init_options);
-        let model = model.unwrap();
-        
-        FastEmbeddingsClient {
-            model
+    /// Load one of the models supported by fastembed, downloading it first
+    /// if it is not in the local model cache, unless offline is set
+    pub fn new(model: &Option<String>, offline: bool) -> Result<Self> {
+        let model_info = match model {
+            Some(name) => find_model(name)?,
+            None => TextEmbedding::get_model_info(&DEFAULT_FASTEMBED_MODEL)?.clone(),
+        };
+
+        if offline {
+            let missing = missing_files(&get_cache_path(), &model_info);
+            if !missing.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "Model {} is missing {} in {} and --offline was given\n\
+                     Run csep once with network access to download it, copy the model cache \
+                     from another machine or load a local ONNX model with --model-path",
+                    model_name(&model_info),
+                    missing.join(", "),
+                    get_cache_path().display()
+                ));
+            }
+        }
+
+        let init_options = InitOptions::new(model_info.model.clone()).with_show_download_progress(true).with_cache_dir(get_cache_path());
+        let model = TextEmbedding::try_new(init_options).map_err(|err| {
+            anyhow::anyhow!("Error loading fastembed model {}: {}", model_name(&model_info), err)
+        })?;
+
+        Ok(FastEmbeddingsClient {
+            model,
+            name: model_name(&model_info),
+            dimensions: model_info.dim,
+        })
+    }
+
+    /// Load an ONNX embedding model and its tokenizer from a directory,
+    /// this never touches the network
+    pub fn from_dir(dir: &Path, config: &FastEmbedConfig) -> Result<Self> {
+        let (onnx_file, onnx_bytes, tokenizer_files) = read_local_model(dir)?;
+        let name = local_model_name(dir, &onnx_file)?;
+
+        let user_model = UserDefinedEmbeddingModel::new(onnx_bytes, tokenizer_files)
+            .with_pooling(parse_pooling(&config.pooling)?);
+        let mut init_options = InitOptionsUserDefined::new();
+        if let Some(max_length) = config.max_length {
+            init_options = init_options.with_max_length(max_length);
         }
+        let model = TextEmbedding::try_new_from_user_defined(user_model, init_options)
+            .map_err(|err| anyhow::anyhow!("Error loading local model {}: {}", dir.display(), err))?;
+
+        // A local model has no model info to read the dimensions from
+        let dimensions = probed_dimensions(&name, &model.embed(vec!["dimensions"], None)?)?;
+
+        Ok(FastEmbeddingsClient {
+            model,
+            name,
+            dimensions,
+        })
     }
 }
 
@@ -40,4 +247,105 @@ impl EmbeddingsClient for FastEmbeddingsClient {
 
         Ok(embeddings)
     }
+
+    fn name(&self) -> &str {
+        "fastembed"
+    }
+
+    fn model(&self) -> &str {
+        &self.name
+    }
+
+    fn dimensions(&self) -> Option<usize> {
+        Some(self.dimensions)
+    }
+
+    fn tokens(&self, text: &str) -> Option<usize> {
+        let encoding = self.model.tokenizer.encode(text, false).ok()?;
+        Some(encoding.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_model() {
+        let default = find_model("Qdrant/all-MiniLM-L6-v2-onnx").unwrap();
+        assert_eq!(default.model, DEFAULT_FASTEMBED_MODEL);
+
+        let bge = find_model("bge-small-en-v1.5").unwrap();
+        assert_eq!(bge.model, EmbeddingModel::BGESmallENV15);
+        assert_eq!(bge.dim, 384);
+
+        let e5 = find_model("INTFLOAT/MULTILINGUAL-E5-SMALL").unwrap();
+        assert_eq!(e5.model, EmbeddingModel::MultilingualE5Small);
+
+        let quantized = find_model("nomic-ai/nomic-embed-text-v1.5-Q").unwrap();
+        assert_eq!(quantized.model, EmbeddingModel::NomicEmbedTextV15Q);
+        let full = find_model("nomic-embed-text-v1.5").unwrap();
+        assert_eq!(full.model, EmbeddingModel::NomicEmbedTextV15);
+    }
+
+    #[test]
+    fn test_find_unknown_model() {
+        let err = find_model("bge-small").unwrap_err().to_string();
+        assert!(err.contains("Unknown fastembed model bge-small"), "{}", err);
+        assert!(err.contains("Xenova/bge-small-en-v1.5"), "{}", err);
+        assert!(err.contains("--list-models"), "{}", err);
+    }
+
+    #[test]
+    fn test_missing_files() {
+        let cache = tempfile::tempdir().unwrap();
+        let info = TextEmbedding::get_model_info(&DEFAULT_FASTEMBED_MODEL).unwrap();
+        assert_eq!(missing_files(cache.path(), info).len(), 5);
+
+        let repo_dir = cache.path().join("models--Qdrant--all-MiniLM-L6-v2-onnx");
+        let snapshot = repo_dir.join("snapshots").join("abc123");
+        fs::create_dir_all(repo_dir.join("refs")).unwrap();
+        fs::create_dir_all(&snapshot).unwrap();
+        fs::write(repo_dir.join("refs").join("main"), "abc123").unwrap();
+        for file in std::iter::once(info.model_file.as_str()).chain(TOKENIZER_FILES) {
+            fs::write(snapshot.join(file), "").unwrap();
+        }
+        assert!(missing_files(cache.path(), info).is_empty());
+
+        fs::remove_file(snapshot.join("tokenizer.json")).unwrap();
+        assert_eq!(missing_files(cache.path(), info), vec!["tokenizer.json"]);
+    }
+
+    #[test]
+    fn test_read_local_model() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in TOKENIZER_FILES {
+            fs::write(dir.path().join(file), file).unwrap();
+        }
+
+        let err = read_local_model(dir.path()).unwrap_err().to_string();
+        assert!(err.contains("Could not find the ONNX model"), "{}", err);
+
+        fs::write(dir.path().join("fine-tuned.onnx"), "onnx").unwrap();
+        let (onnx_file, onnx_bytes, tokenizer_files) = read_local_model(dir.path()).unwrap();
+        assert_eq!(onnx_file, dir.path().join("fine-tuned.onnx"));
+        assert_eq!(onnx_bytes, b"onnx");
+        assert_eq!(tokenizer_files.config_file, b"config.json");
+
+        let name = local_model_name(dir.path(), &onnx_file).unwrap();
+        fs::write(&onnx_file, "retrained onnx").unwrap();
+        assert_ne!(name, local_model_name(dir.path(), &onnx_file).unwrap());
+    }
+
+    #[test]
+    fn test_model_names_are_unique() {
+        let mut names: Vec<String> = TextEmbedding::list_supported_models()
+            .iter()
+            .map(model_name)
+            .collect();
+        let count = names.len();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), count);
+    }
 }
@@ -0,0 +1,106 @@
+use std::{net::IpAddr, time::Duration};
+
+use anyhow::Result;
+use reqwest::{header::CONTENT_TYPE, RequestBuilder, StatusCode};
+use reqwest::Url;
+use serde::Serialize;
+use tracing::warn;
+
+/// Delay before the first retry, doubled for every retry after that
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(250);
+
+fn retry_delay(attempt: u32) -> Duration {
+    RETRY_BASE_DELAY * 2u32.saturating_pow(attempt)
+}
+
+/// Whether a failed request is worth trying again
+fn is_retryable(err: &reqwest::Error) -> bool {
+    err.is_connect() || err.is_timeout() || err.is_request()
+}
+
+/// Send a JSON body, retrying with exponential backoff on connection
+/// errors and server errors, and return the status and body of the final
+/// response
+pub async fn post_json(
+    request: RequestBuilder,
+    body: &impl Serialize,
+    retries: u32,
+) -> Result<(StatusCode, String)> {
+    let request = request
+        .header(CONTENT_TYPE, "application/json")
+        .body(serde_json::to_string(body)?);
+    let mut attempt = 0;
+    loop {
+        let attempt_request = request
+            .try_clone()
+            .ok_or(anyhow::anyhow!("Request can not be retried"))?;
+        let error = match attempt_request.send().await {
+            Ok(response) => {
+                let status = response.status();
+                let url = response.url().to_string();
+                let text = response.text().await?;
+                if !status.is_server_error() || attempt >= retries {
+                    return Ok((status, text));
+                }
+                error_for(&url, status, &text)
+            }
+            Err(err) if is_retryable(&err) => {
+                let error = anyhow::anyhow!("Request failed: {}", err);
+                if attempt >= retries {
+                    return Err(error);
+                }
+                error
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let delay = retry_delay(attempt);
+        warn!("{}, retrying in {:?}", error, delay);
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// Error for an unsuccessful response, including the status and whatever
+/// the server said about it
+pub fn error_for(url: &str, status: StatusCode, body: &str) -> anyhow::Error {
+    anyhow::anyhow!("{} returned {}: {}", url, status, body.trim())
+}
+
+/// Whether a URL points at this machine, so that it can be used without
+/// network access
+pub fn is_local_url(url: &str) -> bool {
+    let Ok(url) = Url::parse(url) else {
+        return false;
+    };
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+        Ok(ip) => ip.is_loopback() || ip.is_unspecified(),
+        Err(_) => host.eq_ignore_ascii_case("localhost"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(0), Duration::from_millis(250));
+        assert_eq!(retry_delay(1), Duration::from_millis(500));
+        assert_eq!(retry_delay(3), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_is_local_url() {
+        assert!(is_local_url("http://localhost:11434"));
+        assert!(is_local_url("http://127.0.0.1:8080/v1"));
+        assert!(is_local_url("http://0.0.0.0:11434"));
+        assert!(is_local_url("http://[::1]:11434"));
+        assert!(!is_local_url("http://gpu-box:11434"));
+        assert!(!is_local_url("https://api.openai.com/v1"));
+        assert!(!is_local_url("not a url"));
+    }
+}
@@ -1,23 +1,22 @@
 use anyhow::Result;
 use async_trait::async_trait;
-use serde::{Deserialize, Serialize};
 
 use self::ollama::OllamaEmbeddingsClient;
 use self::fastembed::FastEmbeddingsClient;
+use self::openai::OpenAiEmbeddingsClient;
 
 pub mod ollama;
 pub mod fastembed;
+pub mod openai;
+pub mod http;
+#[cfg(test)]
+pub mod fake;
 
-#[derive(Debug, Serialize, Deserialize)]
-struct EmbeddingsRequest {
-    input: String,
-    model: String,
-}
-
-#[allow(dead_code)]
+#[allow(dead_code, clippy::large_enum_variant)]
 pub enum EmbeddingsClientImpl {
     Ollama(OllamaEmbeddingsClient),
     FastEmbed(FastEmbeddingsClient),
+    OpenAi(OpenAiEmbeddingsClient),
 }
 
 #[async_trait]
@@ -26,6 +25,39 @@ impl EmbeddingsClient for EmbeddingsClientImpl {
         match self {
             EmbeddingsClientImpl::Ollama(client) => client.get_embeddings(text).await,
             EmbeddingsClientImpl::FastEmbed(client) => client.get_embeddings(text).await,
+            EmbeddingsClientImpl::OpenAi(client) => client.get_embeddings(text).await,
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            EmbeddingsClientImpl::Ollama(client) => client.name(),
+            EmbeddingsClientImpl::FastEmbed(client) => client.name(),
+            EmbeddingsClientImpl::OpenAi(client) => client.name(),
+        }
+    }
+
+    fn model(&self) -> &str {
+        match self {
+            EmbeddingsClientImpl::Ollama(client) => client.model(),
+            EmbeddingsClientImpl::FastEmbed(client) => client.model(),
+            EmbeddingsClientImpl::OpenAi(client) => client.model(),
+        }
+    }
+
+    fn dimensions(&self) -> Option<usize> {
+        match self {
+            EmbeddingsClientImpl::Ollama(client) => client.dimensions(),
+            EmbeddingsClientImpl::FastEmbed(client) => client.dimensions(),
+            EmbeddingsClientImpl::OpenAi(client) => client.dimensions(),
+        }
+    }
+
+    fn tokens(&self, text: &str) -> Option<usize> {
+        match self {
+            EmbeddingsClientImpl::Ollama(client) => client.tokens(text),
+            EmbeddingsClientImpl::FastEmbed(client) => client.tokens(text),
+            EmbeddingsClientImpl::OpenAi(client) => client.tokens(text),
         }
     }
 }
@@ -33,5 +65,31 @@ impl EmbeddingsClient for EmbeddingsClientImpl {
 #[async_trait]
 pub trait EmbeddingsClient {
     async fn get_embeddings(&self, text: &[&str]) -> Result<Vec<Vec<f32>>>;
+
+    /// Name of the client, as passed to --client
+    fn name(&self) -> &str;
+
+    /// Name of the model used to generate embeddings
+    fn model(&self) -> &str;
+
+    /// Length of the embedding vectors produced by the model, if the
+    /// client knows it without embedding a probe text
+    fn dimensions(&self) -> Option<usize> {
+        None
+    }
+
+    /// Number of tokens the model's own tokenizer splits text into, None
+    /// if the tokenizer is not available to the client
+    fn tokens(&self, _text: &str) -> Option<usize> {
+        None
+    }
 }
 
+/// Length of the embedding of a probe text, an error if the model returned
+/// none, as every vector of the index must have at least one dimension
+pub fn probed_dimensions(model: &str, embeddings: &[Vec<f32>]) -> Result<usize> {
+    match embeddings.first() {
+        Some(embedding) if !embedding.is_empty() => Ok(embedding.len()),
+        _ => Err(anyhow::anyhow!("Model {} returned no embeddings", model)),
+    }
+}
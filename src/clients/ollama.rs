@@ -1,29 +1,98 @@
-use tracing::{error, info};
-use anyhow::Result;
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use tracing::{info, warn};
+use anyhow::{Context, Result};
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use async_trait::async_trait;  // Add this dependency in your `Cargo.toml`
+use tokio::sync::Semaphore;
+
+use crate::config::OllamaConfig;
+
+use super::{
+    http::{error_for, post_json},
+    EmbeddingsClient,
+};
 
-use super::EmbeddingsClient;
+pub const DEFAULT_OLLAMA_URL: &str = "http://localhost:11434";
+pub const DEFAULT_OLLAMA_MODEL: &str = "all-minilm";
+const DEFAULT_OLLAMA_PORT: u16 = 11434;
+const DEFAULT_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_RETRIES: u32 = 3;
+const DEFAULT_BATCH_SIZE: usize = 32;
+const DEFAULT_CONCURRENCY: usize = 4;
 
 pub struct OllamaEmbeddingsClient {
-    base_url: &'static str,
+    base_url: String,
     model: String,
+    client: reqwest::Client,
+    retries: u32,
+    batch_size: usize,
+    /// Limits the number of requests in flight across all callers
+    in_flight: Semaphore,
+    /// Set once the server turns out not to support /api/embed, after
+    /// which the legacy /api/embeddings endpoint is used for every text
+    legacy: AtomicBool,
+}
+
+/// Turn an Ollama host as it may be given in OLLAMA_HOST, like
+/// `0.0.0.0` or `gpu-box:8080`, into a base URL. Values that already have
+/// a scheme are used as is.
+pub fn normalize_url(host: &str) -> String {
+    let host = host.trim().trim_end_matches('/');
+    if host.contains("://") {
+        return host.to_string();
+    }
+    let has_port = host
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+    if has_port {
+        format!("http://{}", host)
+    } else {
+        format!("http://{}:{}", host, DEFAULT_OLLAMA_PORT)
+    }
+}
+
+/// Pick the Ollama base URL, --ollama-url takes precedence over the
+/// OLLAMA_HOST environment variable, which takes precedence over the
+/// config file
+pub fn resolve_url(
+    cli_url: &Option<String>,
+    env_host: Option<String>,
+    config: &OllamaConfig,
+) -> String {
+    cli_url
+        .clone()
+        .or(env_host.filter(|host| !host.trim().is_empty()))
+        .or(config.url.clone())
+        .map(|url| normalize_url(&url))
+        .unwrap_or(DEFAULT_OLLAMA_URL.to_string())
 }
 
 impl OllamaEmbeddingsClient {
-    pub fn new(model: &Option<String>) -> Self {
+    pub fn new(model: &Option<String>, base_url: String, config: &OllamaConfig) -> Result<Self> {
         let model = model.clone();
-        OllamaEmbeddingsClient {
-            base_url: "http://localhost:11434",
-            model: model.unwrap_or("all-minilm".to_string()),
-        }
+        let timeout = Duration::from_secs(config.timeout.unwrap_or(DEFAULT_TIMEOUT_SECS));
+        let client = reqwest::Client::builder().timeout(timeout).build()?;
+        Ok(OllamaEmbeddingsClient {
+            base_url,
+            model: model.unwrap_or(DEFAULT_OLLAMA_MODEL.to_string()),
+            client,
+            retries: config.retries.unwrap_or(DEFAULT_RETRIES),
+            batch_size: config.batch_size.unwrap_or(DEFAULT_BATCH_SIZE).max(1),
+            in_flight: Semaphore::new(config.concurrency.unwrap_or(DEFAULT_CONCURRENCY).max(1)),
+            legacy: AtomicBool::new(false),
+        })
     }
 }
 
 #[derive(Debug, Serialize)]
-struct OllamaRequest {
-    model: String,
-    prompt: String,
+struct OllamaRequest<'a> {
+    model: &'a str,
+    prompt: &'a str,
 }
 
 #[derive(Deserialize)]
@@ -31,46 +100,334 @@ struct OllamaResponse {
     embedding: Vec<f32>,
 }
 
+#[derive(Debug, Serialize)]
+struct OllamaEmbedRequest<'a> {
+    model: &'a str,
+    input: &'a [&'a str],
+}
+
+#[derive(Deserialize)]
+struct OllamaEmbedResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+/// Body of an error returned by Ollama itself, as opposed to a 404 for a
+/// route that an older server doesn't have
+#[derive(Deserialize)]
+struct OllamaError {
+    #[allow(dead_code)]
+    error: String,
+}
+
+/// Models and the dimensions of their embeddings
 /// Benchmark leaderboard: https://huggingface.co/spaces/mteb/leaderboard
-pub const OLLAMA_MODELS: [&str; 3] = ["all-minilm", "mxbai-embed-large", "nomic-embed-text"];
-
-async fn get_one(request: OllamaRequest, base_url: &str) -> Result<OllamaResponse> {
-    info!("Request: {:?}", request.prompt);
-    let url = format!("{}/api/embeddings", base_url);
-    let client = reqwest::Client::new();
-    let request_body = serde_json::to_string(&request)?;
-    let response = client.post(url).body(request_body).send().await?;
-    let ollama_response = response.text().await?;
-    let response_object: OllamaResponse = serde_json::from_str(&ollama_response)?;
-    Ok(response_object)
+pub const OLLAMA_MODELS: [(&str, usize); 3] = [
+    ("all-minilm", 384),
+    ("mxbai-embed-large", 1024),
+    ("nomic-embed-text", 768),
+];
+
+#[derive(Deserialize)]
+struct OllamaTagsResponse {
+    models: Vec<OllamaTag>,
 }
 
+#[derive(Deserialize)]
+struct OllamaTag {
+    name: String,
+}
+
+impl OllamaEmbeddingsClient {
+    /// Names of the models that have been pulled on the server, without
+    /// the :latest tag
+    pub async fn local_models(&self) -> Result<Vec<String>> {
+        let url = format!("{}/api/tags", self.base_url);
+        let response = self.client.get(&url).send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            return Err(error_for(&url, status, &body));
+        }
+        let tags: OllamaTagsResponse = serde_json::from_str(&body)?;
+        Ok(tags
+            .models
+            .into_iter()
+            .map(|tag| match tag.name.strip_suffix(":latest") {
+                Some(name) => name.to_string(),
+                None => tag.name,
+            })
+            .collect())
+    }
+
+    async fn post(&self, path: &str, body: &impl Serialize) -> Result<(StatusCode, String)> {
+        let url = format!("{}{}", self.base_url, path);
+        post_json(self.client.post(&url), body, self.retries)
+            .await
+            .with_context(|| format!("Could not reach Ollama at {}", self.base_url))
+    }
+
+    /// Embed a batch of texts in a single request to /api/embed, returns
+    /// None if the server doesn't support it
+    async fn embed_batch(&self, batch: &[&str]) -> Result<Option<Vec<Vec<f32>>>> {
+        let request = OllamaEmbedRequest {
+            model: &self.model,
+            input: batch,
+        };
+        let (status, body) = self.post("/api/embed", &request).await?;
+
+        if status == StatusCode::NOT_FOUND && serde_json::from_str::<OllamaError>(&body).is_err() {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            return Err(error_for(&format!("{}/api/embed", self.base_url), status, &body));
+        }
+
+        let response: OllamaEmbedResponse = serde_json::from_str(&body)
+            .with_context(|| format!("Unexpected response from Ollama: {}", body.trim()))?;
+        if response.embeddings.len() != batch.len() {
+            return Err(anyhow::anyhow!(
+                "Ollama returned {} embeddings for {} texts",
+                response.embeddings.len(),
+                batch.len()
+            ));
+        }
+        Ok(Some(response.embeddings))
+    }
+
+    /// Embed a single text with the legacy /api/embeddings endpoint
+    async fn get_one(&self, text: &str) -> Result<Vec<f32>> {
+        info!("Request: {:?}", text);
+        let request = OllamaRequest {
+            model: &self.model,
+            prompt: text,
+        };
+        let (status, body) = self.post("/api/embeddings", &request).await?;
+        if !status.is_success() {
+            return Err(error_for(&format!("{}/api/embeddings", self.base_url), status, &body));
+        }
+        let response: OllamaResponse = serde_json::from_str(&body)
+            .with_context(|| format!("Unexpected response from Ollama: {}", body.trim()))?;
+        Ok(response.embedding)
+    }
+
+    async fn get_batch(&self, batch: &[&str]) -> Result<Vec<Vec<f32>>> {
+        if !self.legacy.load(Ordering::Relaxed) {
+            let _permit = self.in_flight.acquire().await?;
+            if let Some(embeddings) = self.embed_batch(batch).await? {
+                return Ok(embeddings);
+            }
+            warn!("Ollama at {} does not support /api/embed, falling back to /api/embeddings", self.base_url);
+            self.legacy.store(true, Ordering::Relaxed);
+        }
+
+        let futs = batch.iter().map(|text| async move {
+            let _permit = self.in_flight.acquire().await?;
+            self.get_one(text).await
+        });
+        futures::future::join_all(futs).await.into_iter().collect()
+    }
+}
 
 #[async_trait]
 impl EmbeddingsClient for OllamaEmbeddingsClient {
     async fn get_embeddings(&self, text: &[&str]) -> Result<Vec<Vec<f32>>> {
-        let futs: Vec<_> = text.iter().map(|&t| {
-            let request = OllamaRequest {
-                model: self.model.to_string(),
-                prompt: t.to_string(),
-            };
-            get_one(request, self.base_url)
-        }).collect();
-
+        let futs = text.chunks(self.batch_size).map(|batch| self.get_batch(batch));
         let responses = futures::future::join_all(futs).await;
 
         let mut embeddings = Vec::new();
 
         for response in responses {
-            match response {
-                Ok(r) => embeddings.push(r.embedding),
-                Err(e) => {
-                    error!("Error in response object: {}", e);
-                    return Err(anyhow::anyhow!("Error in response object"))
-                }
-            }
+            embeddings.extend(response?);
         }
 
         Ok(embeddings)
     }
+
+    fn name(&self) -> &str {
+        "ollama"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, Request, Respond, ResponseTemplate,
+    };
+
+    use super::*;
+
+    /// Embeds each input as its length and the length of the request it
+    /// arrived in, so tests can check order and batching
+    struct EmbedResponder;
+
+    impl Respond for EmbedResponder {
+        fn respond(&self, request: &Request) -> ResponseTemplate {
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            let input = body["input"].as_array().unwrap();
+            let embeddings: Vec<Vec<f32>> = input
+                .iter()
+                .map(|text| vec![text.as_str().unwrap().len() as f32, input.len() as f32])
+                .collect();
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({ "embeddings": embeddings }))
+        }
+    }
+
+    struct LegacyResponder;
+
+    impl Respond for LegacyResponder {
+        fn respond(&self, request: &Request) -> ResponseTemplate {
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            let prompt = body["prompt"].as_str().unwrap();
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({ "embedding": [prompt.len() as f32, 1.0] }))
+        }
+    }
+
+    fn client_for(server: &MockServer, batch_size: usize) -> OllamaEmbeddingsClient {
+        let config = OllamaConfig {
+            batch_size: Some(batch_size),
+            retries: Some(1),
+            ..Default::default()
+        };
+        OllamaEmbeddingsClient::new(&None, server.uri(), &config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_batched_embed() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/embed"))
+            .respond_with(EmbedResponder)
+            .expect(3)
+            .mount(&server)
+            .await;
+
+        let client = client_for(&server, 2);
+        let embeddings = client
+            .get_embeddings(&["a", "bb", "ccc", "dddd", "eeeee"])
+            .await
+            .unwrap();
+        assert_eq!(
+            embeddings,
+            vec![
+                vec![1.0, 2.0],
+                vec![2.0, 2.0],
+                vec![3.0, 2.0],
+                vec![4.0, 2.0],
+                vec![5.0, 1.0],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_legacy_endpoint() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/embed"))
+            .respond_with(ResponseTemplate::new(404).set_body_string("404 page not found"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/embeddings"))
+            .respond_with(LegacyResponder)
+            .expect(3)
+            .mount(&server)
+            .await;
+
+        let client = client_for(&server, 2);
+        let embeddings = client.get_embeddings(&["a", "bb"]).await.unwrap();
+        assert_eq!(embeddings, vec![vec![1.0, 1.0], vec![2.0, 1.0]]);
+
+        // Once the fallback has happened /api/embed isn't tried again
+        let embeddings = client.get_embeddings(&["ccc"]).await.unwrap();
+        assert_eq!(embeddings, vec![vec![3.0, 1.0]]);
+    }
+
+    #[tokio::test]
+    async fn test_retries_server_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/embed"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/embed"))
+            .respond_with(EmbedResponder)
+            .mount(&server)
+            .await;
+
+        let client = client_for(&server, 2);
+        let embeddings = client.get_embeddings(&["a"]).await.unwrap();
+        assert_eq!(embeddings, vec![vec![1.0, 1.0]]);
+    }
+
+    #[tokio::test]
+    async fn test_local_models() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/tags"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "models": [
+                    { "name": "all-minilm:latest" },
+                    { "name": "nomic-embed-text:v1.5" },
+                ]
+            })))
+            .mount(&server)
+            .await;
+
+        let client = client_for(&server, 2);
+        assert_eq!(
+            client.local_models().await.unwrap(),
+            vec!["all-minilm".to_string(), "nomic-embed-text:v1.5".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_includes_status_and_body() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/embed"))
+            .respond_with(
+                ResponseTemplate::new(404)
+                    .set_body_string(r#"{"error":"model \"all-minilm\" not found, try pulling it first"}"#),
+            )
+            .mount(&server)
+            .await;
+
+        let client = client_for(&server, 2);
+        let err = client.get_embeddings(&["a"]).await.unwrap_err().to_string();
+        assert!(err.contains("404 Not Found"), "{}", err);
+        assert!(err.contains("try pulling it first"), "{}", err);
+    }
+
+    #[test]
+    fn test_normalize_url() {
+        assert_eq!(normalize_url("0.0.0.0"), "http://0.0.0.0:11434");
+        assert_eq!(normalize_url("gpu-box:8080"), "http://gpu-box:8080");
+        assert_eq!(normalize_url("https://ollama.example.com/"), "https://ollama.example.com");
+        assert_eq!(normalize_url("http://localhost:11434"), "http://localhost:11434");
+    }
+
+    #[test]
+    fn test_resolve_url() {
+        let config = OllamaConfig {
+            url: Some("http://config:11434".to_string()),
+            ..Default::default()
+        };
+        let cli = Some("http://cli:11434".to_string());
+        let env = Some("env".to_string());
+
+        assert_eq!(resolve_url(&cli, env.clone(), &config), "http://cli:11434");
+        assert_eq!(resolve_url(&None, env, &config), "http://env:11434");
+        assert_eq!(resolve_url(&None, Some("".to_string()), &config), "http://config:11434");
+        assert_eq!(resolve_url(&None, None, &OllamaConfig::default()), DEFAULT_OLLAMA_URL);
+    }
 }
@@ -0,0 +1,328 @@
+use std::{env, time::Duration};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+
+use crate::config::OpenAiConfig;
+
+use super::{
+    http::{error_for, post_json},
+    EmbeddingsClient,
+};
+
+pub const DEFAULT_OPENAI_URL: &str = "https://api.openai.com/v1";
+pub const DEFAULT_OPENAI_MODEL: &str = "text-embedding-3-small";
+
+/// Models offered by OpenAI itself and the default dimensions of their
+/// embeddings, other servers have their own models
+pub const OPENAI_MODELS: [(&str, usize); 3] = [
+    ("text-embedding-3-small", 1536),
+    ("text-embedding-3-large", 3072),
+    ("text-embedding-ada-002", 1536),
+];
+
+const DEFAULT_API_KEY_ENV: &str = "OPENAI_API_KEY";
+const DEFAULT_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_RETRIES: u32 = 3;
+const DEFAULT_BATCH_SIZE: usize = 64;
+const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Client for any server that implements the OpenAI /v1/embeddings API,
+/// such as OpenAI itself, llama.cpp server, vLLM or LM Studio
+pub struct OpenAiEmbeddingsClient {
+    base_url: String,
+    model: String,
+    api_key: Option<String>,
+    dimensions: Option<usize>,
+    client: reqwest::Client,
+    retries: u32,
+    batch_size: usize,
+    /// Limits the number of requests in flight across all callers
+    in_flight: Semaphore,
+}
+
+/// Pick the base URL, --openai-url takes precedence over the
+/// OPENAI_BASE_URL environment variable, which takes precedence over the
+/// config file
+pub fn resolve_url(
+    cli_url: &Option<String>,
+    env_url: Option<String>,
+    config: &OpenAiConfig,
+) -> String {
+    cli_url
+        .clone()
+        .or(env_url.filter(|url| !url.trim().is_empty()))
+        .or(config.url.clone())
+        .map(|url| url.trim().trim_end_matches('/').to_string())
+        .unwrap_or(DEFAULT_OPENAI_URL.to_string())
+}
+
+impl OpenAiEmbeddingsClient {
+    pub fn new(model: &Option<String>, base_url: String, config: &OpenAiConfig) -> Result<Self> {
+        let model = model
+            .clone()
+            .or(config.model.clone())
+            .unwrap_or(DEFAULT_OPENAI_MODEL.to_string());
+        let api_key_env = config.api_key_env.as_deref().unwrap_or(DEFAULT_API_KEY_ENV);
+        let api_key = env::var(api_key_env).ok().filter(|key| !key.is_empty());
+
+        let timeout = Duration::from_secs(config.timeout.unwrap_or(DEFAULT_TIMEOUT_SECS));
+        let client = reqwest::Client::builder().timeout(timeout).build()?;
+
+        Ok(OpenAiEmbeddingsClient {
+            base_url,
+            model,
+            api_key,
+            dimensions: config.dimensions,
+            client,
+            retries: config.retries.unwrap_or(DEFAULT_RETRIES),
+            batch_size: config.batch_size.unwrap_or(DEFAULT_BATCH_SIZE).max(1),
+            in_flight: Semaphore::new(config.concurrency.unwrap_or(DEFAULT_CONCURRENCY).max(1)),
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct EmbeddingsRequest<'a> {
+    model: &'a str,
+    input: &'a [&'a str],
+    encoding_format: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dimensions: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingsResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+impl OpenAiEmbeddingsClient {
+    async fn get_batch(&self, batch: &[&str]) -> Result<Vec<Vec<f32>>> {
+        let _permit = self.in_flight.acquire().await?;
+
+        let url = format!("{}/embeddings", self.base_url);
+        let mut request = self.client.post(&url);
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        let body = EmbeddingsRequest {
+            model: &self.model,
+            input: batch,
+            encoding_format: "float",
+            dimensions: self.dimensions,
+        };
+
+        let (status, body) = post_json(request, &body, self.retries)
+            .await
+            .with_context(|| format!("Could not reach {}", self.base_url))?;
+        if !status.is_success() {
+            return Err(error_for(&url, status, &body));
+        }
+
+        let mut response: EmbeddingsResponse = serde_json::from_str(&body)
+            .with_context(|| format!("Unexpected response from {}: {}", url, body.trim()))?;
+        if response.data.len() != batch.len() {
+            return Err(anyhow::anyhow!(
+                "{} returned {} embeddings for {} texts",
+                url,
+                response.data.len(),
+                batch.len()
+            ));
+        }
+
+        // The embeddings may come back in any order
+        response.data.sort_by_key(|data| data.index);
+        Ok(response.data.into_iter().map(|data| data.embedding).collect())
+    }
+}
+
+#[async_trait]
+impl EmbeddingsClient for OpenAiEmbeddingsClient {
+    async fn get_embeddings(&self, text: &[&str]) -> Result<Vec<Vec<f32>>> {
+        let futs = text.chunks(self.batch_size).map(|batch| self.get_batch(batch));
+        let responses = futures::future::join_all(futs).await;
+
+        let mut embeddings = Vec::new();
+        for response in responses {
+            embeddings.extend(response?);
+        }
+
+        Ok(embeddings)
+    }
+
+    fn name(&self) -> &str {
+        "openai"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn dimensions(&self) -> Option<usize> {
+        self.dimensions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{body_partial_json, header, method, path},
+        Mock, MockServer, Request, Respond, ResponseTemplate,
+    };
+
+    use super::*;
+    use crate::index::CacheKey;
+
+    /// Embeds each input as its length and the length of the request it
+    /// arrived in, returned in reverse order to check they are sorted
+    struct EmbeddingsResponder;
+
+    impl Respond for EmbeddingsResponder {
+        fn respond(&self, request: &Request) -> ResponseTemplate {
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            let input = body["input"].as_array().unwrap();
+            let data: Vec<serde_json::Value> = input
+                .iter()
+                .enumerate()
+                .rev()
+                .map(|(index, text)| {
+                    serde_json::json!({
+                        "object": "embedding",
+                        "index": index,
+                        "embedding": [text.as_str().unwrap().len() as f32, input.len() as f32],
+                    })
+                })
+                .collect();
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "object": "list",
+                "data": data,
+                "model": body["model"],
+            }))
+        }
+    }
+
+    fn client_for(server: &MockServer, config: OpenAiConfig) -> OpenAiEmbeddingsClient {
+        let config = OpenAiConfig {
+            retries: Some(0),
+            ..config
+        };
+        OpenAiEmbeddingsClient::new(&None, format!("{}/v1", server.uri()), &config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_batched_embeddings() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/embeddings"))
+            .and(body_partial_json(serde_json::json!({
+                "model": DEFAULT_OPENAI_MODEL,
+                "dimensions": 256,
+            })))
+            .respond_with(EmbeddingsResponder)
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let client = client_for(
+            &server,
+            OpenAiConfig {
+                batch_size: Some(2),
+                dimensions: Some(256),
+                ..Default::default()
+            },
+        );
+        let embeddings = client.get_embeddings(&["a", "bb", "ccc"]).await.unwrap();
+        assert_eq!(
+            embeddings,
+            vec![vec![1.0, 2.0], vec![2.0, 2.0], vec![3.0, 1.0]]
+        );
+        assert_eq!(client.dimensions(), Some(256));
+    }
+
+    #[tokio::test]
+    async fn test_empty_embedding_has_no_dimensions() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/embeddings"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": [{ "index": 0, "embedding": [] }],
+            })))
+            .mount(&server)
+            .await;
+
+        let cache = tempfile::tempdir().unwrap();
+        let client = client_for(&server, OpenAiConfig::default());
+        let err = CacheKey::new_in(cache.path(), &client).await.unwrap_err().to_string();
+        assert!(err.contains("returned no embeddings"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_sends_api_key() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/embeddings"))
+            .and(header("authorization", "Bearer secret"))
+            .respond_with(EmbeddingsResponder)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        env::set_var("CSEP_TEST_OPENAI_KEY", "secret");
+        let client = client_for(
+            &server,
+            OpenAiConfig {
+                api_key_env: Some("CSEP_TEST_OPENAI_KEY".to_string()),
+                ..Default::default()
+            },
+        );
+        let embeddings = client.get_embeddings(&["a"]).await.unwrap();
+        assert_eq!(embeddings, vec![vec![1.0, 1.0]]);
+    }
+
+    #[tokio::test]
+    async fn test_error_includes_status_and_body() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/embeddings"))
+            .respond_with(
+                ResponseTemplate::new(401)
+                    .set_body_string(r#"{"error":{"message":"Incorrect API key provided"}}"#),
+            )
+            .mount(&server)
+            .await;
+
+        let client = client_for(&server, OpenAiConfig::default());
+        let err = client.get_embeddings(&["a"]).await.unwrap_err().to_string();
+        assert!(err.contains("401 Unauthorized"), "{}", err);
+        assert!(err.contains("Incorrect API key provided"), "{}", err);
+    }
+
+    #[test]
+    fn test_resolve_url() {
+        let config = OpenAiConfig {
+            url: Some("http://gateway/v1/".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(resolve_url(&None, None, &config), "http://gateway/v1");
+        assert_eq!(
+            resolve_url(&None, Some("http://env/v1".to_string()), &config),
+            "http://env/v1"
+        );
+        assert_eq!(
+            resolve_url(&Some("http://localhost:8080/v1".to_string()), None, &config),
+            "http://localhost:8080/v1"
+        );
+        assert_eq!(
+            resolve_url(&None, None, &OpenAiConfig::default()),
+            DEFAULT_OPENAI_URL
+        );
+    }
+}
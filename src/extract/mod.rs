@@ -0,0 +1,31 @@
+use anyhow::Result;
+
+mod ooxml;
+mod pdf;
+mod zip;
+
+/// Separates the pages of extracted text, like it does in the output of
+/// pdftotext
+pub const PAGE_BREAK: char = '\x0c';
+
+/// Extensions of the formats that text can be extracted from without a
+/// preprocessor
+pub const EXTENSIONS: [&str; 4] = ["pdf", "docx", "pptx", "xlsx"];
+
+/// Text of a document in one of the formats in [EXTENSIONS], with its pages
+/// separated by [PAGE_BREAK]
+pub fn extract(data: &[u8], extension: &str) -> Result<String> {
+    match extension {
+        "pdf" => pdf::extract(data),
+        _ => ooxml::extract(data, extension),
+    }
+}
+
+/// What the pages of a document with an extension are called
+pub fn page_unit(extension: &str) -> &'static str {
+    match extension {
+        "pptx" | "ppt" | "odp" | "key" => "slide",
+        "xlsx" | "xls" | "ods" => "sheet",
+        _ => "page",
+    }
+}
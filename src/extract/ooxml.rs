@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+
+use super::{zip::Archive, PAGE_BREAK};
+
+/// A tag or the text between tags in an XML document
+enum Token<'a> {
+    /// Local name of the element, without its namespace prefix, and the
+    /// element itself to read its attributes from
+    Open(&'a str, &'a BytesStart<'a>),
+    Close(&'a str),
+    Text(&'a str),
+}
+
+/// Go through the tags and text of an XML document in order. Self closing
+/// tags are an open tag followed by a close tag.
+fn tokens(xml: &str, mut visit: impl FnMut(Token<'_>)) -> Result<()> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().expand_empty_elements = true;
+    loop {
+        match reader.read_event()? {
+            Event::Start(start) => {
+                let name = std::str::from_utf8(start.local_name().into_inner())?;
+                visit(Token::Open(name, &start));
+            }
+            Event::End(end) => {
+                visit(Token::Close(std::str::from_utf8(end.local_name().into_inner())?));
+            }
+            Event::Text(text) => visit(Token::Text(&text.unescape()?)),
+            Event::CData(cdata) => visit(Token::Text(&String::from_utf8_lossy(&cdata))),
+            Event::Eof => return Ok(()),
+            _ => {}
+        }
+    }
+}
+
+/// Value of an attribute, matched by its local name
+fn attribute(element: &BytesStart, name: &str) -> Option<String> {
+    element
+        .attributes()
+        .flatten()
+        .find(|attribute| attribute.key.local_name().as_ref() == name.as_bytes())
+        .and_then(|attribute| attribute.unescape_value().ok())
+        .map(|value| value.into_owned())
+}
+
+/// Number at the end of a part name like `ppt/slides/slide12.xml`
+fn part_number(name: &str) -> Option<u32> {
+    let stem = name.strip_suffix(".xml")?;
+    let digits = stem.len() - stem.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    stem[stem.len() - digits..].parse().ok()
+}
+
+/// Parts of an archive in a directory that are numbered like
+/// `slide1.xml`, in the order of their numbers
+fn numbered_parts(archive: &Archive, prefix: &str) -> Vec<String> {
+    let mut parts: Vec<(u32, String)> = archive
+        .names()
+        .filter(|name| name.starts_with(prefix) && !name[prefix.len()..].contains('/'))
+        .filter_map(|name| Some((part_number(name)?, name.to_string())))
+        .collect();
+    parts.sort();
+    parts.into_iter().map(|(_, name)| name).collect()
+}
+
+/// Id of the relationship an element refers to, its `r:id` attribute
+fn relationship_id(element: &BytesStart) -> Option<String> {
+    element
+        .attributes()
+        .flatten()
+        .find(|attribute| {
+            attribute.key.prefix().is_some() && attribute.key.local_name().as_ref() == b"id"
+        })
+        .and_then(|attribute| attribute.unescape_value().ok())
+        .map(|value| value.into_owned())
+}
+
+/// Parts listed in order by the main part of a document, like the sheets
+/// of a workbook, found through the relationships of the main part. Files
+/// without the list fall back to the numbered parts under a prefix.
+fn listed_parts(
+    archive: &mut Archive,
+    main: &str,
+    element: &str,
+    prefix: &str,
+) -> Result<Vec<String>> {
+    let (dir, file) = main.rsplit_once('/').unwrap_or(("", main));
+    let rels = format!("{}/_rels/{}.rels", dir, file);
+    let (Some(main_xml), Some(rels_xml)) = (archive.read(main)?, archive.read(&rels)?) else {
+        return Ok(numbered_parts(archive, prefix));
+    };
+
+    // Targets are relative to the directory of the main part, unless they
+    // start at the root of the archive
+    let mut targets = HashMap::new();
+    tokens(&String::from_utf8_lossy(&rels_xml), |token| {
+        if let Token::Open("Relationship", relationship) = token {
+            let id = attribute(relationship, "Id");
+            if let (Some(id), Some(target)) = (id, attribute(relationship, "Target")) {
+                let target = match target.strip_prefix('/') {
+                    Some(target) => target.to_string(),
+                    None => format!("{}/{}", dir, target),
+                };
+                targets.insert(id, target);
+            }
+        }
+    })?;
+
+    let mut parts = Vec::new();
+    tokens(&String::from_utf8_lossy(&main_xml), |token| match token {
+        Token::Open(name, listed) if name == element => {
+            if let Some(target) = relationship_id(listed).and_then(|id| targets.get(&id)) {
+                parts.push(target.clone());
+            }
+        }
+        _ => {}
+    })?;
+    Ok(parts)
+}
+
+fn read_part(archive: &mut Archive, name: &str) -> Result<String> {
+    let part = archive
+        .read(name)?
+        .with_context(|| format!("Missing {}", name))?;
+    Ok(String::from_utf8_lossy(&part).to_string())
+}
+
+/// Append a line break unless the text already ends with one
+fn end_line(text: &mut String) {
+    if !text.is_empty() && !text.ends_with(['\n', PAGE_BREAK]) {
+        text.push('\n');
+    }
+}
+
+/// Text of the paragraphs of a Word document. Word has no pages of its
+/// own, so pages are where it broke them when the document was last saved
+/// and explicit page breaks. Word marks where it broke a page right after
+/// an explicit break too, which is not another page.
+fn docx(archive: &mut Archive) -> Result<String> {
+    let xml = read_part(archive, "word/document.xml")?;
+    let mut text = String::new();
+    let mut in_text = false;
+    tokens(&xml, |token| match token {
+        Token::Open("t", _) => in_text = true,
+        Token::Close("t") => in_text = false,
+        Token::Text(content) if in_text => text.push_str(content),
+        Token::Open("tab", _) => text.push('\t'),
+        Token::Open("br", element) => {
+            if attribute(element, "type").as_deref() == Some("page") {
+                text.push(PAGE_BREAK);
+            } else {
+                text.push('\n');
+            }
+        }
+        Token::Open("lastRenderedPageBreak", _) => {
+            let before = text.trim_end_matches(|c: char| c != PAGE_BREAK && c.is_whitespace());
+            if !before.ends_with(PAGE_BREAK) {
+                text.push(PAGE_BREAK);
+            }
+        }
+        Token::Close("p") => end_line(&mut text),
+        _ => {}
+    })?;
+    Ok(text)
+}
+
+/// Text of each slide of a presentation, a page per slide in the order
+/// they are shown
+fn pptx(archive: &mut Archive) -> Result<String> {
+    let mut slides = Vec::new();
+    let names = listed_parts(archive, "ppt/presentation.xml", "sldId", "ppt/slides/slide")?;
+    for name in names {
+        let xml = read_part(archive, &name)?;
+        let mut text = String::new();
+        let mut in_text = false;
+        tokens(&xml, |token| match token {
+            Token::Open("t", _) => in_text = true,
+            Token::Close("t") => in_text = false,
+            Token::Text(content) if in_text => text.push_str(content),
+            Token::Open("br", _) | Token::Close("p") => end_line(&mut text),
+            _ => {}
+        })?;
+        slides.push(text);
+    }
+    Ok(slides.join(&PAGE_BREAK.to_string()))
+}
+
+/// Cells of each sheet of a workbook, a row per line and a page per sheet
+/// in the order of their tabs
+fn xlsx(archive: &mut Archive) -> Result<String> {
+    // Most text in a workbook is stored once and referenced by index
+    let mut shared_strings = Vec::new();
+    if let Some(part) = archive.read("xl/sharedStrings.xml")? {
+        let xml = String::from_utf8_lossy(&part);
+        let mut in_text = false;
+        tokens(&xml, |token| match token {
+            Token::Open("si", _) => shared_strings.push(String::new()),
+            Token::Open("t", _) => in_text = true,
+            Token::Close("t") => in_text = false,
+            Token::Text(content) if in_text => {
+                if let Some(string) = shared_strings.last_mut() {
+                    string.push_str(content);
+                }
+            }
+            _ => {}
+        })?;
+    }
+
+    let mut sheets = Vec::new();
+    for name in listed_parts(archive, "xl/workbook.xml", "sheet", "xl/worksheets/sheet")? {
+        let xml = read_part(archive, &name)?;
+        let mut text = String::new();
+        let mut cell_type = None;
+        let mut in_value = false;
+        let mut row_has_cells = false;
+        tokens(&xml, |token| match token {
+            Token::Open("c", element) => {
+                cell_type = attribute(element, "t");
+                if row_has_cells {
+                    text.push('\t');
+                }
+            }
+            Token::Open("v", _) | Token::Open("t", _) => in_value = true,
+            Token::Close("v") | Token::Close("t") => in_value = false,
+            Token::Text(content) if in_value => {
+                row_has_cells = true;
+                match cell_type.as_deref() {
+                    Some("s") => {
+                        let string = content
+                            .trim()
+                            .parse::<usize>()
+                            .ok()
+                            .and_then(|index| shared_strings.get(index));
+                        text.push_str(string.map_or("", |string| string.as_str()));
+                    }
+                    _ => text.push_str(content),
+                }
+            }
+            Token::Close("row") => {
+                if row_has_cells {
+                    text.push('\n');
+                }
+                row_has_cells = false;
+            }
+            _ => {}
+        })?;
+        sheets.push(text);
+    }
+    Ok(sheets.join(&PAGE_BREAK.to_string()))
+}
+
+/// Text of a Word document, PowerPoint presentation or Excel workbook
+pub fn extract(data: &[u8], extension: &str) -> Result<String> {
+    let mut archive = Archive::new(data)?;
+    match extension {
+        "docx" => docx(&mut archive),
+        "pptx" => pptx(&mut archive),
+        "xlsx" => xlsx(&mut archive),
+        _ => Err(anyhow::anyhow!("Not an Office document: {}", extension)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::zip, *};
+
+    #[test]
+    fn test_tokens() {
+        let mut seen = Vec::new();
+        tokens(
+            "<?xml version=\"1.0\"?><!-- note --><w:p w:rsidR=\"1\"><w:t>a &lt; b &#x263A;</w:t><w:br w:type=\"page\"/></w:p>",
+            |token| {
+                seen.push(match token {
+                    Token::Open(name, element) => {
+                        format!("<{} {:?}>", name, attribute(element, "type"))
+                    }
+                    Token::Close(name) => format!("</{}>", name),
+                    Token::Text(text) => text.to_string(),
+                })
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            seen,
+            vec![
+                "<p None>",
+                "<t None>",
+                "a < b \u{263a}",
+                "</t>",
+                "<br Some(\"page\")>",
+                "</br>",
+                "</p>"
+            ]
+        );
+        assert!(tokens("<w:p></w:r>", |_| {}).is_err());
+    }
+
+    #[test]
+    fn test_docx() {
+        let data = zip::write(&[(
+            "word/document.xml",
+            "<w:document><w:body><w:p><w:r><w:t>Design</w:t></w:r><w:r><w:t xml:space=\"preserve\"> doc</w:t></w:r></w:p><w:p><w:r><w:br w:type=\"page\"/></w:r></w:p><w:p><w:r><w:lastRenderedPageBreak/><w:t>Second page</w:t></w:r></w:p><w:p><w:r><w:lastRenderedPageBreak/><w:t>Third</w:t></w:r></w:p></w:body></w:document>",
+        )]);
+        assert_eq!(
+            extract(&data, "docx").unwrap(),
+            "Design doc\n\u{c}Second page\n\u{c}Third\n"
+        );
+    }
+
+    #[test]
+    fn test_pptx() {
+        let slide = |text: &str| {
+            format!(
+                "<p:sld><p:cSld><p:spTree><p:sp><p:txBody><a:p><a:r><a:t>{}</a:t></a:r></a:p><a:p><a:r><a:t>Notes &amp; more</a:t></a:r></a:p></p:txBody></p:sp></p:spTree></p:cSld></p:sld>",
+                text
+            )
+        };
+        let (first, second, tenth) = (slide("Intro"), slide("Roadmap"), slide("Questions"));
+        let data = zip::write(&[
+            ("ppt/slides/slide10.xml", &tenth),
+            ("ppt/slides/slide2.xml", &second),
+            ("ppt/slides/slide1.xml", &first),
+            ("ppt/slides/_rels/slide1.xml.rels", "<Relationships/>"),
+        ]);
+        let text = extract(&data, "pptx").unwrap();
+        let slides: Vec<&str> = text.split(PAGE_BREAK).collect();
+        assert_eq!(
+            slides,
+            vec![
+                "Intro\nNotes & more\n",
+                "Roadmap\nNotes & more\n",
+                "Questions\nNotes & more\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_xlsx() {
+        let mut parts = vec![
+            (
+                "xl/sharedStrings.xml",
+                "<sst><si><t>Item</t></si><si><t>Cost</t></si><si><r><t>Lap</t></r><r><t>top</t></r></si></sst>",
+            ),
+            (
+                "xl/worksheets/sheet1.xml",
+                "<worksheet><sheetData><row r=\"1\"><c r=\"A1\" t=\"s\"><v>0</v></c><c r=\"B1\" t=\"s\"><v>1</v></c></row><row r=\"2\"><c r=\"A2\" t=\"s\"><v>2</v></c><c r=\"B2\"><v>1299.5</v></c></row></sheetData></worksheet>",
+            ),
+            (
+                "xl/worksheets/sheet2.xml",
+                "<worksheet><sheetData><row><c t=\"inlineStr\"><is><t>Inline</t></is></c></row></sheetData></worksheet>",
+            ),
+        ];
+        assert_eq!(
+            extract(&zip::write(&parts), "xlsx").unwrap(),
+            "Item\tCost\nLaptop\t1299.5\n\u{c}Inline\n"
+        );
+
+        // The tabs were moved, so the sheets are in a different order than
+        // their parts
+        parts.push((
+            "xl/workbook.xml",
+            "<workbook xmlns:r=\"r\"><sheets><sheet name=\"Notes\" sheetId=\"2\" r:id=\"rId7\"/><sheet name=\"Costs\" sheetId=\"1\" r:id=\"rId1\"/></sheets></workbook>",
+        ));
+        parts.push((
+            "xl/_rels/workbook.xml.rels",
+            "<Relationships><Relationship Id=\"rId1\" Target=\"worksheets/sheet1.xml\"/><Relationship Id=\"rId7\" Target=\"/xl/worksheets/sheet2.xml\"/></Relationships>",
+        ));
+        assert_eq!(
+            extract(&zip::write(&parts), "xlsx").unwrap(),
+            "Inline\n\u{c}Item\tCost\nLaptop\t1299.5\n"
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use encoding_rs::WINDOWS_1252;
+use lopdf::{content::Content, Document, Encoding, Object, ObjectId};
+use tracing::warn;
+
+use super::PAGE_BREAK;
+
+/// How deeply pages may be nested in the page tree before a file is taken
+/// to be broken
+const MAX_DEPTH: usize = 64;
+
+/// A TJ adjustment moving the next glyph further than this, in thousandths
+/// of the font size, is taken to be a space between words
+const WORD_GAP: f32 = 250.0;
+
+/// Text of a string drawn with a font, or with no font the resources know of
+fn decode(encoding: Option<&Encoding>, bytes: &[u8]) -> String {
+    encoding
+        .and_then(|encoding| Document::decode_text(encoding, bytes).ok())
+        .unwrap_or_else(|| WINDOWS_1252.decode_without_bom_handling(bytes).0.into_owned())
+}
+
+fn number(object: Option<&Object>) -> Option<f32> {
+    object.and_then(|object| object.as_float().ok())
+}
+
+fn new_line(text: &mut String) {
+    let trimmed = text.trim_end_matches(' ').len();
+    text.truncate(trimmed);
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+fn end_word(text: &mut String) {
+    if !text.is_empty() && !text.ends_with([' ', '\n']) {
+        text.push(' ');
+    }
+}
+
+/// Encodings of the fonts a page can use, by name. Resources are inherited
+/// from the page tree, with those nearer the page taking precedence.
+fn encodings(document: &Document, page: ObjectId) -> BTreeMap<Vec<u8>, Encoding<'_>> {
+    let mut encodings = BTreeMap::new();
+    let mut node = document.get_dictionary(page).ok();
+    let mut depth = 0;
+    while let Some(dictionary) = node.filter(|_| depth < MAX_DEPTH) {
+        let fonts = dictionary
+            .get_deref(b"Resources", document)
+            .and_then(Object::as_dict)
+            .and_then(|resources| resources.get_deref(b"Font", document))
+            .and_then(Object::as_dict);
+        for (name, font) in fonts.iter().flat_map(|fonts| fonts.iter()) {
+            if encodings.contains_key(name) {
+                continue;
+            }
+            let font = document.dereference(font).and_then(|(_, font)| font.as_dict());
+            if let Ok(encoding) = font.and_then(|font| font.get_font_encoding(document)) {
+                encodings.insert(name.clone(), encoding);
+            }
+        }
+        node = dictionary.get_deref(b"Parent", document).and_then(Object::as_dict).ok();
+        depth += 1;
+    }
+    encodings
+}
+
+/// Text shown by the operators of a page, with a line break wherever it
+/// moves to a new line
+fn page_text(document: &Document, page: ObjectId) -> Result<String> {
+    // Fonts the page can't tell the encoding of fall back to Windows-1252
+    let encodings = encodings(document, page);
+    let content = Content::decode(&document.get_page_content(page)?)?;
+
+    let mut text = String::new();
+    let mut encoding = None;
+    let mut line_y = None;
+    for operation in &content.operations {
+        let operands = &operation.operands;
+        match operation.operator.as_str() {
+            "Tf" => {
+                if let Some(Ok(name)) = operands.first().map(Object::as_name) {
+                    encoding = encodings.get(name);
+                }
+            }
+            "Tj" | "'" | "\"" => {
+                if operation.operator != "Tj" {
+                    new_line(&mut text);
+                }
+                if let Some(Ok(string)) = operands.last().map(Object::as_str) {
+                    text.push_str(&decode(encoding, string));
+                }
+            }
+            "TJ" => {
+                if let Some(Ok(parts)) = operands.last().map(Object::as_array) {
+                    for part in parts {
+                        match part {
+                            Object::String(string, _) => text.push_str(&decode(encoding, string)),
+                            _ if number(Some(part)).is_some_and(|gap| gap < -WORD_GAP) => {
+                                end_word(&mut text)
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            "Td" | "TD" if number(operands.get(1)).is_some_and(|y| y != 0.0) => {
+                new_line(&mut text)
+            }
+            "T*" => new_line(&mut text),
+            "Tm" => {
+                let y = number(operands.get(5));
+                if line_y.is_some() && y != line_y {
+                    new_line(&mut text);
+                } else {
+                    end_word(&mut text);
+                }
+                line_y = y;
+            }
+            "ET" => end_word(&mut text),
+            _ => {}
+        }
+    }
+
+    // Page breaks are what tell the pages apart
+    let text = text.replace(PAGE_BREAK, " ");
+    Ok(text.lines().map(str::trim_end).collect::<Vec<_>>().join("\n").trim().to_string())
+}
+
+/// Text of every page of a PDF file. Only text drawn with fonts that say
+/// what characters they draw can be extracted, scanned pages have none.
+pub fn extract(data: &[u8]) -> Result<String> {
+    let mut document = Document::load_mem(data).context("Unsupported or broken PDF file")?;
+    // Files that are encrypted only to restrict what may be done with them
+    // open with an empty password
+    if document.is_encrypted() {
+        document.decrypt("").context("PDF file is encrypted")?;
+    }
+    let pages = document.get_pages();
+    if pages.is_empty() {
+        return Err(anyhow::anyhow!("No pages found")).context("Unsupported or broken PDF file");
+    }
+    Ok(pages
+        .into_iter()
+        .map(|(number, page)| {
+            page_text(&document, page).unwrap_or_else(|err| {
+                warn!("Skipping page {} of PDF file: {}", number, err);
+                String::new()
+            })
+        })
+        .collect::<Vec<_>>()
+        .join(&PAGE_BREAK.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use lopdf::{dictionary, Stream};
+
+    use super::*;
+
+    #[test]
+    fn test_extract() {
+        let mut document = Document::with_version("1.4");
+        let pages_id = document.new_object_id();
+        let win_ansi = document.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "Encoding" => "WinAnsiEncoding",
+        });
+        let cmap = b"/CIDInit /ProcSet findresource begin\n12 dict begin\nbegincmap\n/CMapName /Adobe-Identity-UCS def\n/CMapType 2 def\n1 begincodespacerange\n<0000> <FFFF>\nendcodespacerange\n1 beginbfchar\n<0000> <00E9>\nendbfchar\n1 beginbfrange\n<0001> <0002> <0041>\nendbfrange\nendcmap\nCMapName currentdict /CMap defineresource pop\nend\nend\n";
+        let to_unicode = document.add_object(Stream::new(dictionary! {}, cmap.to_vec()));
+        let composite = document.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type0",
+            "Encoding" => "Identity-H",
+            "ToUnicode" => to_unicode,
+        });
+
+        let contents: [&[u8]; 2] = [
+            b"BT /F1 12 Tf 72 700 Td (Hello \\(PDF\\) caf\\351) Tj 0 -14 Td [(Sec) 20 (ond) -300 (line)] TJ ET\nBI /W 1 /H 1 ID \x00\xff EI\n",
+            b"BT /F2 12 Tf <000000010002> Tj ET",
+        ];
+        let mut kids = Vec::new();
+        for content in contents {
+            let mut stream = Stream::new(dictionary! {}, content.to_vec());
+            stream.compress().unwrap();
+            let content = document.add_object(stream);
+            kids.push(Object::Reference(document.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "Contents" => content,
+            })));
+        }
+        // The fonts are inherited from the page tree
+        document.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => kids,
+                "Count" => 2,
+                "Resources" => dictionary! {
+                    "Font" => dictionary! { "F1" => win_ansi, "F2" => composite },
+                },
+            }),
+        );
+        let catalog = document.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        document.trailer.set("Root", catalog);
+        let mut data = Vec::new();
+        document.save_to(&mut data).unwrap();
+
+        let text = extract(&data).unwrap();
+        let pages: Vec<&str> = text.split(PAGE_BREAK).collect();
+        assert_eq!(pages, vec!["Hello (PDF) caf\u{e9}\nSecond line", "\u{e9}AB"]);
+
+        assert!(extract(b"%PDF-1.4\n%%EOF").is_err());
+        assert!(extract(b"just some text").is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use std::io::{Cursor, Read};
+
+use anyhow::{Context, Result};
+use zip::{result::ZipError, ZipArchive};
+
+/// Most bytes read out of an archive in total. The parts of real Office
+/// documents are far smaller, while a few kilobytes of zip bomb can
+/// inflate to gigabytes.
+pub const MAX_INFLATED_BYTES: u64 = 256 * 1024 * 1024;
+
+/// The files of a ZIP archive, read up to a total of MAX_INFLATED_BYTES
+pub struct Archive<'a> {
+    zip: ZipArchive<Cursor<&'a [u8]>>,
+    /// Most bytes read in total
+    limit: u64,
+    /// Bytes that may still be read
+    budget: u64,
+}
+
+impl<'a> Archive<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        Self::with_limit(data, MAX_INFLATED_BYTES)
+    }
+
+    fn with_limit(data: &'a [u8], limit: u64) -> Result<Self> {
+        let zip = ZipArchive::new(Cursor::new(data)).context("Not a ZIP archive")?;
+        Ok(Archive {
+            zip,
+            limit,
+            budget: limit,
+        })
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.zip.file_names()
+    }
+
+    /// Contents of the file with the given name, None if there is none
+    pub fn read(&mut self, name: &str) -> Result<Option<Vec<u8>>> {
+        let file = match self.zip.by_name(name) {
+            Ok(file) => file,
+            Err(ZipError::FileNotFound) => return Ok(None),
+            Err(err) => return Err(err).with_context(|| format!("Corrupt ZIP entry {}", name)),
+        };
+        // The size in the header can't be trusted, so stop reading one byte
+        // past the budget rather than relying on it
+        let mut contents = Vec::new();
+        file.take(self.budget + 1)
+            .read_to_end(&mut contents)
+            .with_context(|| format!("Corrupt ZIP entry {}", name))?;
+        let read = contents.len() as u64;
+        if read > self.budget {
+            return Err(anyhow::anyhow!(
+                "ZIP archive inflates to more than {} bytes",
+                self.limit
+            ));
+        }
+        self.budget -= read;
+        Ok(Some(contents))
+    }
+}
+
+/// Write a ZIP archive for tests, deflating the files whose names end in
+/// .xml
+#[cfg(test)]
+pub fn write(files: &[(&str, &str)]) -> Vec<u8> {
+    use std::io::Write;
+
+    use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, contents) in files {
+        let method = if name.ends_with(".xml") {
+            CompressionMethod::Deflated
+        } else {
+            CompressionMethod::Stored
+        };
+        zip.start_file(*name, SimpleFileOptions::default().compression_method(method))
+            .unwrap();
+        zip.write_all(contents.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let data = write(&[
+            ("mimetype", "application/test"),
+            ("word/document.xml", "<w:document>hello</w:document>"),
+        ]);
+        let mut archive = Archive::new(&data).unwrap();
+        let mut names: Vec<&str> = archive.names().collect();
+        names.sort();
+        assert_eq!(names, vec!["mimetype", "word/document.xml"]);
+        assert_eq!(archive.read("mimetype").unwrap().unwrap(), b"application/test");
+        assert_eq!(
+            archive.read("word/document.xml").unwrap().unwrap(),
+            b"<w:document>hello</w:document>"
+        );
+        assert!(archive.read("missing.xml").unwrap().is_none());
+
+        assert!(Archive::new(b"not a zip file at all, just some text").is_err());
+        assert!(Archive::new(&data[..data.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn test_budget() {
+        // A megabyte of spaces deflates to a kilobyte or so
+        let spaces = " ".repeat(1024 * 1024);
+        let data = write(&[("a.xml", &spaces), ("b.xml", "<b/>")]);
+        assert!(data.len() < 10 * 1024);
+
+        let mut archive = Archive::with_limit(&data, 1024 * 1024).unwrap();
+        assert_eq!(archive.read("a.xml").unwrap().unwrap().len(), 1024 * 1024);
+        assert!(archive.read("b.xml").is_err());
+
+        let mut archive = Archive::with_limit(&data, 1000).unwrap();
+        let err = archive.read("a.xml").unwrap_err();
+        assert!(err.to_string().contains("inflates to more than"));
+    }
+}
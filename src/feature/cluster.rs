@@ -0,0 +1,193 @@
+use std::cmp::Reverse;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{
+    args::ClusterMethod,
+    chunker::Chunking,
+    cluster::{agglomerative, kmeans, representative, AGGLOMERATIVE_LIMIT},
+    clients::{EmbeddingsClient, EmbeddingsClientImpl},
+    feature::default::{open_indexes, report_skipped},
+    files::WalkOptions,
+    index::SkippedFile,
+    similarity::normalize,
+    utils::one_line,
+};
+
+/// Longest the chunks of files are shown as in the text format
+const PREVIEW_CHARS: usize = 80;
+
+/// What is clustered
+pub enum Input<'a> {
+    /// Lines of text, which are embedded
+    Lines(Vec<String>),
+    /// The chunks of the files under these paths, whose embeddings are
+    /// taken from the cache
+    Paths(&'a [String]),
+}
+
+pub struct ClusterOptions<'a> {
+    /// Agglomerative for lines and k-means for chunks of files if not given
+    pub method: Option<ClusterMethod>,
+    /// Number of clusters to make
+    pub clusters: Option<usize>,
+    /// Agglomerative clustering without a number of clusters stops merging
+    /// when no two clusters are on average this similar
+    pub min_similarity: f32,
+    /// Which files under the directories are clustered
+    pub walk: &'a WalkOptions,
+    /// How files are split into chunks
+    pub chunking: &'a Chunking,
+    /// Print JSON instead of text
+    pub json: bool,
+}
+
+#[derive(Serialize)]
+struct Item {
+    /// File and line, or page, a chunk starts at
+    #[serde(skip_serializing_if = "Option::is_none")]
+    location: Option<String>,
+    text: String,
+}
+
+impl Item {
+    fn display(&self) -> String {
+        match &self.location {
+            Some(location) => format!("{}: {}", location, one_line(&self.text, PREVIEW_CHARS)),
+            None => self.text.clone(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Cluster<'a> {
+    size: usize,
+    representative: &'a Item,
+    members: Vec<&'a Item>,
+}
+
+#[derive(Serialize)]
+struct Output<'a> {
+    model: &'a str,
+    client: &'a str,
+    clusters: Vec<Cluster<'a>>,
+}
+
+/// Group texts or chunks that are about the same thing, and print every
+/// group with the member most typical of it first
+pub async fn run(
+    embeddings_client: &EmbeddingsClientImpl,
+    input: Input<'_>,
+    options: &ClusterOptions<'_>,
+) -> Result<()> {
+    let mut items = Vec::new();
+    let mut vectors: Vec<&[f32]> = Vec::new();
+    let embeddings;
+    let indexes;
+    // There are usually far more chunks of files than lines, too many to
+    // cluster bottom up
+    let method = options.method.unwrap_or(match input {
+        Input::Lines(_) => ClusterMethod::Agglomerative,
+        Input::Paths(_) => ClusterMethod::Kmeans,
+    });
+    match input {
+        Input::Lines(lines) => {
+            let strs: Vec<&str> = lines.iter().map(String::as_str).collect();
+            // Cached embeddings are normalized already, these aren't
+            let mut lines_embeddings = embeddings_client.get_embeddings(&strs).await?;
+            for embedding in &mut lines_embeddings {
+                normalize(embedding);
+            }
+            embeddings = lines_embeddings;
+            vectors.extend(embeddings.iter().map(Vec::as_slice));
+            items.extend(lines.into_iter().map(|text| Item {
+                location: None,
+                text,
+            }));
+        }
+        Input::Paths(paths) => {
+            indexes = open_indexes(embeddings_client, paths, options.walk, options.chunking).await?;
+            let skipped: Vec<&SkippedFile> =
+                indexes.iter().flat_map(|index| index.skipped()).collect();
+            report_skipped(&skipped);
+            for index in &indexes {
+                for entry in index.searched_files() {
+                    let path = index.display_path(entry);
+                    for (chunk, embedding) in entry.chunks.iter().zip(index.embeddings(entry)) {
+                        items.push(Item {
+                            location: Some(format!(
+                                "{}:{}",
+                                path,
+                                chunk.page.unwrap_or(chunk.start_line)
+                            )),
+                            text: chunk.text.clone(),
+                        });
+                        vectors.push(embedding);
+                    }
+                }
+            }
+        }
+    }
+    if items.is_empty() {
+        return Err(anyhow::anyhow!("Nothing to cluster"));
+    }
+
+    let mut clusters = match method {
+        ClusterMethod::Kmeans => {
+            let k = options
+                .clusters
+                .unwrap_or_else(|| ((items.len() as f32 / 2.0).sqrt().round() as usize).max(1));
+            kmeans(&vectors, k)
+        }
+        ClusterMethod::Agglomerative if items.len() > AGGLOMERATIVE_LIMIT => {
+            return Err(anyhow::anyhow!(
+                "Agglomerative clustering takes at most {} items, got {}. Use --method kmeans instead",
+                AGGLOMERATIVE_LIMIT,
+                items.len()
+            ));
+        }
+        ClusterMethod::Agglomerative => {
+            agglomerative(&vectors, options.clusters, options.min_similarity)
+        }
+    };
+    // Biggest first, ties in the order their first members were given
+    clusters.sort_by_key(|members| (Reverse(members.len()), members.first().copied()));
+
+    let output = Output {
+        model: embeddings_client.model(),
+        client: embeddings_client.name(),
+        clusters: clusters
+            .iter()
+            .map(|members| {
+                let representative = representative(&vectors, members);
+                Cluster {
+                    size: members.len(),
+                    representative: &items[representative],
+                    members: members.iter().map(|&member| &items[member]).collect(),
+                }
+            })
+            .collect(),
+    };
+
+    if options.json {
+        println!("{}", serde_json::to_string(&output)?);
+        return Ok(());
+    }
+    for (i, cluster) in output.clusters.iter().enumerate() {
+        println!(
+            "cluster {}: {} {}",
+            i + 1,
+            cluster.size,
+            if cluster.size == 1 { "item" } else { "items" }
+        );
+        println!("* {}", cluster.representative.display());
+        for member in &cluster.members {
+            if !std::ptr::eq(*member, cluster.representative) {
+                println!("  {}", member.display());
+            }
+        }
+        println!();
+    }
+    Ok(())
+}
@@ -1,31 +1,167 @@
+use std::fmt::Write;
+
 use crate::{
-    chunker::Chunk,
-    clients::{ollama::OllamaEmbeddingsClient, EmbeddingsClient},
-    cosine_similarity,
+    args::MatrixFormat,
+    clients::EmbeddingsClient,
+    similarity::cosine_similarity,
+    utils::one_line,
 };
 use anyhow::Result;
+use serde::Serialize;
 
-pub async fn run(first: String, second: String, model: &Option<String>) -> Result<()> {
-    let model_clone = model.clone();
-    let oec = OllamaEmbeddingsClient::new(&model_clone);
-
-    let first_embeddings = oec.get_embeddings(&[first.as_str()]).await?;
-    let second_embeddings = oec.get_embeddings(&[second.as_str()]).await?;
+/// Longest a text is shown as in the table format
+const LABEL_CHARS: usize = 60;
 
-    let first_chunk = Chunk {
-        line: 0,
-        text: first.clone(),
-        embeddings: first_embeddings[0].to_owned(),
-    };
-    let second_chunk = Chunk {
-        line: 0,
-        text: second.clone(),
-        embeddings: second_embeddings[0].to_owned(),
-    };
+pub async fn run<C: EmbeddingsClient + Sync>(
+    first: String,
+    second: String,
+    embeddings_client: &C,
+) -> Result<()> {
+    let embeddings = embeddings_client
+        .get_embeddings(&[first.as_str(), second.as_str()])
+        .await?;
 
-    let similarity = cosine_similarity(&first_chunk.embeddings, &second_chunk.embeddings);
+    let similarity = cosine_similarity(&embeddings[0], &embeddings[1]);
     println!("first: {}", first);
     println!("second: {}", second);
     println!("similarity: {}", similarity);
     Ok(())
 }
+
+/// Similarity of every pair of texts, in the order they were given
+#[derive(Serialize)]
+struct Matrix<'a> {
+    model: &'a str,
+    client: &'a str,
+    texts: &'a [String],
+    similarities: Vec<Vec<f32>>,
+}
+
+fn similarity_matrix(embeddings: &[Vec<f32>]) -> Vec<Vec<f32>> {
+    embeddings
+        .iter()
+        .map(|a| embeddings.iter().map(|b| cosine_similarity(a, b)).collect())
+        .collect()
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// The texts numbered from 1, followed by the matrix with those numbers
+/// as its headers
+fn table(matrix: &Matrix) -> String {
+    let mut output = String::new();
+    let number_width = matrix.texts.len().to_string().len();
+    for (i, text) in matrix.texts.iter().enumerate() {
+        let label = one_line(text, LABEL_CHARS);
+        let _ = writeln!(output, "{:>width$}  {}", i + 1, label, width = number_width);
+    }
+    output.push('\n');
+
+    let column_width = number_width.max(6);
+    let _ = write!(output, "{:width$}", "", width = number_width);
+    for i in 0..matrix.texts.len() {
+        let _ = write!(output, " {:>width$}", i + 1, width = column_width);
+    }
+    output.push('\n');
+    for (i, row) in matrix.similarities.iter().enumerate() {
+        let _ = write!(output, "{:>width$}", i + 1, width = number_width);
+        for similarity in row {
+            let _ = write!(output, " {:>width$.3}", similarity, width = column_width);
+        }
+        output.push('\n');
+    }
+    output
+}
+
+/// The texts as both the header row and the first column
+fn csv(matrix: &Matrix) -> String {
+    let mut output = String::new();
+    let header: Vec<String> = matrix.texts.iter().map(|text| csv_field(text)).collect();
+    let _ = writeln!(output, ",{}", header.join(","));
+    for (text, row) in matrix.texts.iter().zip(&matrix.similarities) {
+        let row: Vec<String> = row.iter().map(f32::to_string).collect();
+        let _ = writeln!(output, "{},{}", csv_field(text), row.join(","));
+    }
+    output
+}
+
+/// Print the similarity of every pair of a list of texts
+pub async fn run_matrix<C: EmbeddingsClient + Sync>(
+    texts: &[String],
+    format: MatrixFormat,
+    embeddings_client: &C,
+) -> Result<()> {
+    if texts.len() < 2 {
+        return Err(anyhow::anyhow!(
+            "Need at least two texts to compare, got {}",
+            texts.len()
+        ));
+    }
+
+    let strs: Vec<&str> = texts.iter().map(String::as_str).collect();
+    let embeddings = embeddings_client.get_embeddings(&strs).await?;
+    let matrix = Matrix {
+        model: embeddings_client.model(),
+        client: embeddings_client.name(),
+        texts,
+        similarities: similarity_matrix(&embeddings),
+    };
+
+    match format {
+        MatrixFormat::Table => print!("{}", table(&matrix)),
+        MatrixFormat::Csv => print!("{}", csv(&matrix)),
+        MatrixFormat::Json => println!("{}", serde_json::to_string(&matrix)?),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::fake::FakeEmbeddingsClient;
+
+    #[tokio::test]
+    async fn test_matrix() {
+        let texts = vec![
+            "banana".to_string(),
+            "a \"quoted\", long\ntext".to_string(),
+            "ooo".to_string(),
+        ];
+        let client = FakeEmbeddingsClient::new();
+        let strs: Vec<&str> = texts.iter().map(String::as_str).collect();
+        let embeddings = client.get_embeddings(&strs).await.unwrap();
+        let matrix = Matrix {
+            model: client.model(),
+            client: client.name(),
+            texts: &texts,
+            similarities: similarity_matrix(&embeddings),
+        };
+
+        // The matrix is symmetric with ones on the diagonal
+        for (i, row) in matrix.similarities.iter().enumerate() {
+            assert!((row[i] - 1.0).abs() < 1e-6);
+            for (j, similarity) in row.iter().enumerate() {
+                assert_eq!(*similarity, matrix.similarities[j][i]);
+            }
+        }
+        assert_eq!(matrix.similarities[0][2], 0.0);
+
+        let csv = csv(&matrix);
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some(",banana,\"a \"\"quoted\"\", long")
+        );
+        assert!(csv.contains("\nooo,0,"));
+
+        let table = table(&matrix);
+        assert!(table.starts_with("1  banana\n2  a \"quoted\", long text\n3  ooo\n\n"));
+        assert!(table.contains("\n1  1.000"));
+    }
+}
@@ -0,0 +1,198 @@
+use std::env;
+
+use fastembed::TextEmbedding;
+use serde::Serialize;
+
+use crate::{
+    args::{Args, OutputFormat},
+    chunker::DEFAULT_CHUNK_TOKENS,
+    clients::{
+        fastembed::{model_name, DEFAULT_FASTEMBED_MODEL},
+        ollama::{DEFAULT_OLLAMA_MODEL, DEFAULT_OLLAMA_URL},
+        openai::{DEFAULT_OPENAI_MODEL, DEFAULT_OPENAI_URL},
+    },
+    config::Config,
+    hnsw::{DEFAULT_EF, DEFAULT_EF_CONSTRUCTION, DEFAULT_M},
+    DEFAULT_FLOOR,
+};
+
+/// A setting as it is in effect, along with where it came from
+struct Setting {
+    key: String,
+    value: toml::Value,
+    source: String,
+}
+
+fn value<T: Serialize>(value: T) -> Option<toml::Value> {
+    toml::Value::try_from(value).ok()
+}
+
+/// The value of a setting from the command line, an environment variable,
+/// the config files or the default, in that order of precedence
+fn resolve(
+    key: &str,
+    cli: Option<toml::Value>,
+    env: Option<(&str, String)>,
+    config: &Config,
+    default: Option<toml::Value>,
+) -> Option<Setting> {
+    let (value, source) = if let Some(value) = cli {
+        (value, "command line".to_string())
+    } else if let Some((name, value)) = env.filter(|(_, value)| !value.trim().is_empty()) {
+        (toml::Value::String(value), name.to_string())
+    } else if let Some((value, path)) = config.sources.get(key) {
+        (value.clone(), path.display().to_string())
+    } else {
+        (default?, "default".to_string())
+    };
+    Some(Setting {
+        key: key.to_string(),
+        value,
+        source,
+    })
+}
+
+/// Every setting that is in effect, first the ones that can also be given
+/// on the command line and then any others from the config files
+fn settings(args: &Args, config: &Config, env: impl Fn(&str) -> Option<String>) -> Vec<Setting> {
+    let client = args
+        .client
+        .clone()
+        .or(config.client.clone())
+        .unwrap_or("fastembed".to_string());
+    let default_model = match client.as_str() {
+        "ollama" => DEFAULT_OLLAMA_MODEL.to_string(),
+        "openai" => config.openai.model.clone().unwrap_or(DEFAULT_OPENAI_MODEL.to_string()),
+        _ => TextEmbedding::get_model_info(&DEFAULT_FASTEMBED_MODEL)
+            .map(model_name)
+            .unwrap_or_default(),
+    };
+    let format = args.vimgrep.then_some(OutputFormat::Vimgrep).or(args.format);
+    let chunk_cli = |field: &str| match field {
+        "tokens" => args.chunk_tokens.and_then(value),
+        "overlap" => args.chunk_overlap.and_then(value),
+        "sizer" => args.chunk_sizer.and_then(value),
+        _ => None,
+    };
+
+    let mut settings = vec![
+        resolve("client", args.client.clone().and_then(value), None, config, value(&client)),
+        resolve("model", args.model.clone().and_then(value), None, config, value(default_model)),
+        resolve("floor", args.floor.and_then(value), None, config, value(DEFAULT_FLOOR)),
+        resolve("top", args.top.and_then(value), None, config, None),
+        resolve("format", format.and_then(value), None, config, value(OutputFormat::Text)),
+        resolve("include", None, None, config, None),
+        resolve("exclude", None, None, config, None),
+        resolve(
+            "ollama.url",
+            args.ollama_url.clone().and_then(value),
+            env("OLLAMA_HOST").map(|host| ("OLLAMA_HOST", host)),
+            config,
+            value(DEFAULT_OLLAMA_URL),
+        ),
+        resolve(
+            "openai.url",
+            args.openai_url.clone().and_then(value),
+            env("OPENAI_BASE_URL").map(|url| ("OPENAI_BASE_URL", url)),
+            config,
+            value(DEFAULT_OPENAI_URL),
+        ),
+        resolve(
+            "fastembed.model_path",
+            args.model_path.as_ref().and_then(value),
+            None,
+            config,
+            None,
+        ),
+        resolve("chunking.tokens", chunk_cli("tokens"), None, config, value(DEFAULT_CHUNK_TOKENS)),
+        resolve("chunking.overlap", chunk_cli("overlap"), None, config, value(0)),
+        resolve("chunking.sizer", chunk_cli("sizer"), None, config, value("cl100k")),
+        resolve("ann.enabled", args.ann.then_some(true).and_then(value), None, config, value(false)),
+        resolve("ann.m", args.hnsw_m.and_then(value), None, config, value(DEFAULT_M)),
+        resolve(
+            "ann.ef_construction",
+            args.ef_construction.and_then(value),
+            None,
+            config,
+            value(DEFAULT_EF_CONSTRUCTION),
+        ),
+        resolve("ann.ef", args.ef.and_then(value), None, config, value(DEFAULT_EF)),
+    ];
+
+    // The command line settings for chunks apply to every file type
+    for key in config.sources.keys() {
+        if let Some(field) = key
+            .strip_prefix("chunking.extensions.")
+            .and_then(|rest| rest.rsplit_once('.'))
+            .map(|(_, field)| field)
+        {
+            settings.push(resolve(key, chunk_cli(field), None, config, None));
+        }
+    }
+
+    let mut settings: Vec<Setting> = settings.into_iter().flatten().collect();
+    for key in config.sources.keys() {
+        if !settings.iter().any(|setting| setting.key == *key) {
+            settings.extend(resolve(key, None, None, config, None));
+        }
+    }
+    settings
+}
+
+pub fn run(args: &Args, config: &Config) {
+    let settings = settings(args, config, |name| env::var(name).ok());
+
+    let lines: Vec<(String, &str)> = settings
+        .iter()
+        .map(|setting| {
+            (
+                format!("{} = {}", setting.key, setting.value),
+                setting.source.as_str(),
+            )
+        })
+        .collect();
+    let width = lines.iter().map(|(line, _)| line.len()).max().unwrap_or(0);
+    for (line, source) in lines {
+        println!("{:width$}  # {}", line, source, width = width);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn test_settings() {
+        let path = PathBuf::from("/src/app/.csep.toml");
+        let mut config = Config::default();
+        for (key, value) in [
+            ("floor", toml::Value::Float(0.5)),
+            ("ollama.url", toml::Value::String("http://gpu-box:11434".to_string())),
+            ("chunking.extensions.md.tokens", toml::Value::Integer(400)),
+            ("ollama.timeout", toml::Value::Integer(30)),
+        ] {
+            config.sources.insert(key.to_string(), (value, path.clone()));
+        }
+
+        let args = Args::parse_from(["csep", "--client", "ollama", "--chunk-tokens", "50"]);
+        let settings = settings(&args, &config, |name| {
+            (name == "OLLAMA_HOST").then(|| "0.0.0.0".to_string())
+        });
+        let find = |key: &str| {
+            let setting = settings.iter().find(|setting| setting.key == key).unwrap();
+            (setting.value.to_string(), setting.source.as_str())
+        };
+
+        assert_eq!(find("client"), ("\"ollama\"".to_string(), "command line"));
+        assert_eq!(find("model"), ("\"all-minilm\"".to_string(), "default"));
+        assert_eq!(find("floor"), ("0.5".to_string(), "/src/app/.csep.toml"));
+        assert_eq!(find("ollama.url"), ("\"0.0.0.0\"".to_string(), "OLLAMA_HOST"));
+        assert_eq!(find("chunking.extensions.md.tokens"), ("50".to_string(), "command line"));
+        assert_eq!(find("ollama.timeout"), ("30".to_string(), "/src/app/.csep.toml"));
+        assert!(settings.iter().all(|setting| setting.key != "top"));
+    }
+}
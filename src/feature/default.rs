@@ -1,30 +1,159 @@
+use std::{collections::HashMap, path::Path, time::Instant};
+
 use rayon::prelude::*;
+use serde::Serialize;
 use crate::{
-    chunker::{get_chunks_and_embeddings_or_load_from_cache, Chunk},
+    args::OutputFormat,
+    chunker::{Chunk, Chunking},
     clients::{EmbeddingsClient, EmbeddingsClientImpl},
-    files::get_all_files_in_directory,
-    utils::cosine_similarity,
+    extract::page_unit,
+    files::{get_search_roots, WalkOptions},
+    hnsw::{self, Hnsw, HnswParams},
+    index::{CacheKey, FileEntry, Index, SkippedFile},
+    lexical::{bm25, fuse, Fusion, LexicalIndex},
+    similarity::normalized,
+    top_k::TopK,
 };
 use anyhow::Result;
+use tracing::warn;
 
+#[derive(Serialize)]
 pub struct PrintableChunk {
     file: String,
-    chunk: String,
-    line: usize,
+    start_line: usize,
+    start_column: usize,
+    end_line: usize,
+    end_column: usize,
+    start_byte: usize,
+    end_byte: usize,
+    /// Definition the chunk is in, for source files, or the page it is on,
+    /// for documents
+    #[serde(skip_serializing_if = "Option::is_none")]
+    symbol: Option<String>,
+    /// Page, slide or sheet of a document the chunk is on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<usize>,
     similarity: f32,
+    /// Similarity of the embeddings, only set by --explain
+    #[serde(skip_serializing_if = "Option::is_none")]
+    semantic_score: Option<f32>,
+    /// BM25 keyword score, only set by --explain
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lexical_score: Option<f32>,
+    chunk: String,
+    model: String,
+    client: String,
+}
+
+/// Printed after the results by the JSON formats
+#[derive(Serialize)]
+struct Summary<'a> {
+    query: &'a str,
+    model: &'a str,
+    client: &'a str,
+    files_scanned: usize,
+    /// Files that could not be read and why
+    skipped: &'a [&'a SkippedFile],
+    results: usize,
+    elapsed_ms: u128,
+}
+
+/// A line of JSON Lines output, tagged with its type so that results and
+/// the summary can be told apart
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum JsonLine<'a> {
+    Result(&'a PrintableChunk),
+    Summary(&'a Summary<'a>),
+}
+
+#[derive(Serialize)]
+struct JsonDocument<'a> {
+    results: &'a [PrintableChunk],
+    summary: &'a Summary<'a>,
+}
+
+/// A chunk that matched the query along with the scores it was ranked by
+#[derive(Clone, Copy)]
+struct Hit<'a> {
+    index: &'a Index,
+    entry: &'a FileEntry,
+    chunk: &'a Chunk,
+    /// Similarity of the embeddings and BM25 score of the chunk, only
+    /// known in hybrid search
+    components: Option<(f32, f32)>,
+}
+
+/// Definition a chunk of a file is in, or for documents the page it is on,
+/// like `slide 3`
+pub fn chunk_symbol(file: &str, chunk: &Chunk) -> Option<String> {
+    chunk.symbol.clone().or_else(|| {
+        chunk.page.map(|page| {
+            let extension = Path::new(file)
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            format!("{} {}", page_unit(&extension), page)
+        })
+    })
 }
 
 impl PrintableChunk {
+    fn new(
+        file: String,
+        chunk: &Chunk,
+        similarity: f32,
+        components: Option<(f32, f32)>,
+        embeddings_client: &EmbeddingsClientImpl,
+    ) -> Self {
+        PrintableChunk {
+            symbol: chunk_symbol(&file, chunk),
+            file,
+            start_line: chunk.start_line,
+            start_column: chunk.start_column,
+            end_line: chunk.end_line,
+            end_column: chunk.end_column,
+            start_byte: chunk.start,
+            end_byte: chunk.end,
+            page: chunk.page,
+            similarity,
+            semantic_score: components.map(|(semantic, _)| semantic),
+            lexical_score: components.map(|(_, lexical)| lexical),
+            chunk: chunk.text.clone(),
+            model: embeddings_client.model().to_string(),
+            client: embeddings_client.name().to_string(),
+        }
+    }
+
     pub fn print(&self) {
-        println!("file: {}", self.file);
+        match &self.symbol {
+            Some(symbol) => println!("file: {}: {}", self.file, symbol),
+            None => println!("file: {}", self.file),
+        }
         println!("chunk: {}", self.chunk);
-        println!("similarity: {}\n", self.similarity);
+        println!("similarity: {}", self.similarity);
+        if let (Some(semantic), Some(lexical)) = (self.semantic_score, self.lexical_score) {
+            println!("semantic: {}", semantic);
+            println!("bm25: {}", lexical);
+        }
+        println!();
     }
 
 
-    // print in vimgrep compatible format
+    // print in vimgrep compatible format, pointing at the start of the chunk,
+    // or the page it is on for documents
     pub fn print_vimgrep(&self) {
-        println!("{}:{}:0:", self.file, self.line);
+        let (line, column) = match self.page {
+            Some(page) => (page, 1),
+            None => (self.start_line, self.start_column),
+        };
+        println!(
+            "{}:{}:{}:{}",
+            self.file,
+            line,
+            column,
+            self.symbol.as_ref().map_or(String::new(), |symbol| format!(" {}", symbol))
+        );
         // for lines in chunk
         for chunk in self.chunk.lines() {
             println!("  | {}", chunk);
@@ -32,93 +161,438 @@ impl PrintableChunk {
     }
 }
 
-pub async fn run(
-    embeddings_client: &EmbeddingsClientImpl,
-    search_phrase: &str,
-    floor: &f32,
-    no_query: &bool,
-    vimgrep: &bool,
-    should_print: &bool,
-) -> Result<()> {
-    let search_phrase_embeddings = embeddings_client
-        .get_embeddings(&[search_phrase])
-        .await?;
-    let search_phrase_embeddings = &search_phrase_embeddings[0];
+/// How approximate nearest neighbour search is done
+#[derive(Clone, Copy, Debug)]
+pub struct AnnOptions {
+    /// How the graph is built
+    pub params: HnswParams,
+    /// Candidates looked at by every search
+    pub ef: usize,
+}
 
-    let search_chunk = Chunk {
-        line: 0,
-        text: search_phrase.to_string(),
-        embeddings: search_phrase_embeddings.to_owned()
-    };
-    
+pub struct SearchOptions<'a> {
+    /// Files, directories or globs to search
+    pub paths: &'a [String],
+    /// Which files under the directories are searched
+    pub walk: &'a WalkOptions,
+    /// Results at or below this similarity are dropped
+    pub floor: f32,
+    /// Only keep this many of the best results
+    pub top: Option<usize>,
+    /// Only keep this many of the best results from any one file
+    pub max_per_file: Option<usize>,
+    pub no_query: bool,
+    /// Combine the similarity with BM25 keyword scores
+    pub hybrid: Option<Fusion>,
+    /// Search big indexes with a nearest neighbour graph, semantic search
+    /// only
+    pub ann: Option<AnnOptions>,
+    /// Print both scores of hybrid search results
+    pub explain: bool,
+    /// How files are split into chunks
+    pub chunking: &'a Chunking,
+    pub format: OutputFormat,
+    pub should_print: bool,
+}
 
-    // Now lets work with the files in the current directory
-    let current_dir = std::env::current_dir()?.clone();
-    let current_directory = match current_dir.to_str() {
-        Some(dir) => dir,
-        None => panic!("Could not get current directory"),
-    };
-    let files = get_all_files_in_directory(current_directory);
+/// Whether an index is searched with its nearest neighbour graph. Small
+/// indexes are quick to scan, and the graph can't tell the searched files
+/// apart so it is only used when all of them are searched.
+fn use_ann(index: &Index, options: &SearchOptions<'_>) -> bool {
+    options.ann.is_some()
+        && index.rows() >= hnsw::MIN_ROWS
+        && index.searched_rows().iter().all(|&searched| searched)
+}
 
-    let mut printable_chunk = Vec::new();
+/// Rank chunks by the similarity of their embeddings to the query alone
+fn semantic_top<'a>(
+    indexes: &'a [Index],
+    search_phrase_embeddings: &[f32],
+    options: &SearchOptions<'_>,
+) -> TopK<Hit<'a>> {
+    let mut approximate = TopK::new(options.top);
+    let mut exact = Vec::new();
+    for index in indexes {
+        let Some(ann) = options.ann.filter(|_| use_ann(index, options)) else {
+            exact.push(index);
+            continue;
+        };
+        match index.hnsw(ann.params) {
+            Ok(hnsw) => {
+                let top = ann_top(index, &hnsw, search_phrase_embeddings, ann.ef, options);
+                approximate = approximate.merge(top);
+            }
+            Err(err) => {
+                warn!("Error building the nearest neighbour graph, scanning instead: {}", err);
+                exact.push(index);
+            }
+        }
+    }
 
-    let chunk_futures: Vec<_> = files.par_iter().map(|file| {
-         get_chunks_and_embeddings_or_load_from_cache(file.as_str(), embeddings_client)
-    }).collect();
+    // Every index is scored as a whole, in parallel across its rows, then
+    // the best of each file are picked from the scores
+    let mut top = approximate;
+    for index in exact {
+        let similarities = index.similarities(search_phrase_embeddings);
+        for entry in index.searched_files() {
+            let mut file_top = TopK::new(options.max_per_file);
+            for (chunk, &similarity) in entry.chunks.iter().zip(&similarities[entry.first_row..]) {
+                if similarity > options.floor {
+                    let hit = Hit {
+                        index,
+                        entry,
+                        chunk,
+                        components: None,
+                    };
+                    file_top.push(similarity, hit);
+                }
+            }
+            for scored in file_top.into_sorted_vec() {
+                top.push(scored.score, scored.item);
+            }
+        }
+    }
+    top
+}
 
-    let chunk_results = futures::future::join_all(chunk_futures).await;
-    
-    for chunk_result in chunk_results {
-        let chunks = match chunk_result {
-            Ok(chunks) => chunks,
-            Err(err) => {
-                eprintln!("Error chunking file: {}", err);
+/// Rank the chunks of an index that its nearest neighbour graph finds,
+/// looking at enough candidates for --top results. Candidates dropped by
+/// --max-per-file make room for more, so the graph is searched again with
+/// twice as many until there are enough results or nothing left to find.
+fn ann_top<'a>(
+    index: &'a Index,
+    hnsw: &Hnsw,
+    search_phrase_embeddings: &[f32],
+    ef: usize,
+    options: &SearchOptions<'_>,
+) -> TopK<Hit<'a>> {
+    let mut ef = ef.max(options.top.unwrap_or(0)).max(1);
+    loop {
+        let found = index.search_hnsw(hnsw, search_phrase_embeddings, ef);
+        let exhausted = found.len() < ef || ef >= hnsw.rows();
+        let mut top = TopK::new(options.top);
+        let mut results = 0;
+        let mut below_floor = false;
+        let mut per_file: HashMap<&str, usize> = HashMap::new();
+        // Most similar first, so the first ones of a file are its best
+        for (entry, chunk, similarity) in found {
+            if similarity <= options.floor {
+                below_floor = true;
+                break;
+            }
+            let count = per_file.entry(entry.path.as_str()).or_default();
+            if options.max_per_file.is_some_and(|max| *count >= max) {
                 continue;
             }
-        };
+            *count += 1;
+            results += 1;
+            let hit = Hit {
+                index,
+                entry,
+                chunk,
+                components: None,
+            };
+            top.push(similarity, hit);
+        }
+        let enough = options.top.is_none_or(|wanted| results >= wanted);
+        if enough || below_floor || exhausted {
+            return top;
+        }
+        ef *= 2;
+    }
+}
 
-        let printable_chunks = chunks.1.par_iter().filter(|chunk| {
-            let similarity = cosine_similarity(&search_chunk.embeddings, &chunk.embeddings);
-            similarity > *floor
-        });
+/// Rank chunks by a fusion of the similarity of their embeddings and their
+/// BM25 score. Fusion needs the ranks of every candidate, so unlike
+/// semantic search all of them are scored before the best are picked. A
+/// chunk is a candidate if it is above the floor or contains any of the
+/// query terms.
+fn hybrid_top<'a>(
+    indexes: &'a [Index],
+    search_phrase: &str,
+    search_phrase_embeddings: &[f32],
+    fusion: Fusion,
+    options: &SearchOptions<'_>,
+) -> Result<TopK<Hit<'a>>> {
+    let lexical_indexes = indexes
+        .iter()
+        .map(Index::lexical)
+        .collect::<Result<Vec<LexicalIndex>>>()?;
+    let searched_rows: Vec<Vec<bool>> = indexes.iter().map(Index::searched_rows).collect();
+    let similarities: Vec<Vec<f32>> = indexes
+        .iter()
+        .map(|index| index.similarities(search_phrase_embeddings))
+        .collect();
+    let lexical_scores = bm25(
+        &lexical_indexes
+            .iter()
+            .zip(&searched_rows)
+            .map(|(lexical, searched)| (lexical, searched.as_slice()))
+            .collect::<Vec<_>>(),
+        search_phrase,
+    );
 
-        if printable_chunks.clone().count() == 0 {
-            continue;
+    let files: Vec<(usize, &FileEntry)> = indexes
+        .iter()
+        .enumerate()
+        .flat_map(|(i, index)| index.searched_files().into_iter().map(move |entry| (i, entry)))
+        .collect();
+    let candidates: Vec<(Hit, f32, f32)> = files
+        .par_iter()
+        .flat_map_iter(|&(i, entry)| {
+            let index = &indexes[i];
+            let lexical_scores = &lexical_scores[i];
+            let similarities = &similarities[i];
+            entry
+                .chunks
+                .iter()
+                .enumerate()
+                .filter_map(move |(n, chunk)| {
+                    let semantic = similarities[entry.first_row + n];
+                    let lexical = lexical_scores
+                        .get(&(entry.first_row + n))
+                        .copied()
+                        .unwrap_or_default();
+                    let hit = Hit {
+                        index,
+                        entry,
+                        chunk,
+                        components: Some((semantic, lexical)),
+                    };
+                    (semantic > options.floor || lexical > 0.0).then_some((hit, semantic, lexical))
+                })
+        })
+        .collect();
+
+    let scores = fuse(
+        fusion,
+        &candidates
+            .iter()
+            .map(|(_, semantic, lexical)| (*semantic, *lexical))
+            .collect::<Vec<_>>(),
+    );
+
+    // Candidates of the same file are next to each other
+    let mut top = TopK::new(options.top);
+    let scored: Vec<(Hit, f32)> = candidates
+        .into_iter()
+        .zip(scores)
+        .map(|((hit, _, _), score)| (hit, score))
+        .collect();
+    for file_hits in scored.chunk_by(|a, b| std::ptr::eq(a.0.entry, b.0.entry)) {
+        let mut file_top = TopK::new(options.max_per_file);
+        for (hit, score) in file_hits {
+            file_top.push(*score, *hit);
+        }
+        for scored in file_top.into_sorted_vec() {
+            top.push(scored.score, scored.item);
         }
+    }
 
-        let mut printable_chunks = printable_chunks
-            .map(|chunk| PrintableChunk {
-                line: chunk.line,
-                file: chunks.0.clone(),
-                chunk: chunk.text.clone(),
-                similarity: cosine_similarity(&search_chunk.embeddings, &chunk.embeddings),
-            })
-            .collect::<Vec<PrintableChunk>>();
+    Ok(top)
+}
 
-        // Sort by similarity descending
-        printable_chunks.sort_by(|a, b| b.similarity.partial_cmp(&a.similarity).unwrap());
+/// Tell the user about the files that could not be searched, on stderr so
+/// that the results can still be piped somewhere
+pub fn report_skipped(skipped: &[&SkippedFile]) {
+    if skipped.is_empty() {
+        return;
+    }
+    eprintln!(
+        "Skipped {} {} that could not be read:",
+        skipped.len(),
+        if skipped.len() == 1 { "file" } else { "files" }
+    );
+    for file in skipped {
+        eprintln!("  {}: {}", file.path, file.reason);
+    }
+}
+
+/// Indexes of the files under the given paths, or the current directory if
+/// none were given, brought up to date with them
+pub async fn open_indexes(
+    embeddings_client: &EmbeddingsClientImpl,
+    paths: &[String],
+    walk: &WalkOptions,
+    chunking: &Chunking,
+) -> Result<Vec<Index>> {
+    let roots = get_search_roots(paths, walk)?;
+    let cache_key = CacheKey::new(embeddings_client).await?;
+    let chunking = chunking.for_client(embeddings_client);
 
-        printable_chunk.push(printable_chunks);
+    let mut indexes = Vec::new();
+    for root in roots {
+        let mut index = Index::open(&root.path, &cache_key)?;
+        index
+            .update(
+                &root.files,
+                embeddings_client,
+                &chunking,
+                &walk.reader,
+            )
+            .await?;
+        index.add_skipped(root.skipped);
+        indexes.push(index);
     }
+    Ok(indexes)
+}
 
-    if !no_query && !vimgrep {
-        println!("Results for search phrase: {}\n", search_phrase);
+pub async fn run(
+    embeddings_client: &EmbeddingsClientImpl,
+    search_phrase: &str,
+    options: &SearchOptions<'_>,
+) -> Result<()> {
+    let start_time = Instant::now();
+    let search_phrase_embeddings = embeddings_client
+        .get_embeddings(&[search_phrase])
+        .await?;
+    // The embeddings in the index are normalized, so once the query is too
+    // its cosine similarity to them is their dot product
+    let search_phrase_embeddings = &normalized(&search_phrase_embeddings[0]);
+
+    // Now lets work with the files under the given paths, or the current
+    // directory if none were given
+    let indexes = open_indexes(
+        embeddings_client,
+        options.paths,
+        options.walk,
+        options.chunking,
+    )
+    .await?;
+
+    let top = match options.hybrid {
+        Some(fusion) => hybrid_top(
+            &indexes,
+            search_phrase,
+            search_phrase_embeddings,
+            fusion,
+            options,
+        )?,
+        None => semantic_top(&indexes, search_phrase_embeddings, options),
+    };
+
+    let skipped: Vec<&SkippedFile> = indexes.iter().flat_map(|index| index.skipped()).collect();
+    // The JSON formats have them in the summary instead
+    if !options.should_print || matches!(options.format, OutputFormat::Text | OutputFormat::Vimgrep)
+    {
+        report_skipped(&skipped);
     }
 
-    if *should_print {
-        let mut printable_chunk = printable_chunk
-            .par_iter()
-            .flatten()
-            .collect::<Vec<&PrintableChunk>>();
-        printable_chunk.sort_by(|a, b| b.similarity.partial_cmp(&a.similarity).unwrap());
-        for p in printable_chunk {
-            if *vimgrep {
-                p.print_vimgrep();
-                continue;
+    if !options.should_print {
+        return Ok(());
+    }
+
+    let results: Vec<PrintableChunk> = top
+        .into_sorted_vec()
+        .into_iter()
+        .map(|scored| {
+            let hit = scored.item;
+            PrintableChunk::new(
+                hit.index.display_path(hit.entry),
+                hit.chunk,
+                scored.score,
+                hit.components.filter(|_| options.explain),
+                embeddings_client,
+            )
+        })
+        .collect();
+
+    let summary = Summary {
+        query: search_phrase,
+        model: embeddings_client.model(),
+        client: embeddings_client.name(),
+        files_scanned: indexes
+            .iter()
+            .map(|index| index.searched_files().len())
+            .sum(),
+        skipped: &skipped,
+        results: results.len(),
+        elapsed_ms: start_time.elapsed().as_millis(),
+    };
+
+    match options.format {
+        OutputFormat::Text => {
+            if !options.no_query {
+                println!("Results for search phrase: {}\n", search_phrase);
             }
-            p.print();
+            results.iter().for_each(PrintableChunk::print);
+        }
+        OutputFormat::Vimgrep => results.iter().for_each(PrintableChunk::print_vimgrep),
+        OutputFormat::Json => {
+            let document = JsonDocument {
+                results: &results,
+                summary: &summary,
+            };
+            println!("{}", serde_json::to_string(&document)?);
+        }
+        OutputFormat::Jsonl => {
+            for result in &results {
+                println!("{}", serde_json::to_string(&JsonLine::Result(result))?);
+            }
+            println!("{}", serde_json::to_string(&JsonLine::Summary(&summary))?);
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunker::Sizer,
+        clients::fake::FakeEmbeddingsClient,
+        config::ChunkingConfig,
+        files::TextReader,
+        index::tests::cache_key,
+    };
+    use std::fs;
+
+    #[tokio::test]
+    async fn test_ann_top_fills_up_past_max_per_file() {
+        let cache = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let root_str = root.path().to_str().unwrap();
+        // Twenty chunks that match the query exactly in one file, and less
+        // similar ones in two others
+        fs::write(root.path().join("a.txt"), ["aaaa"; 20].join("\n\n")).unwrap();
+        fs::write(root.path().join("b.txt"), "aaae").unwrap();
+        fs::write(root.path().join("c.txt"), "aaee").unwrap();
+        let files: Vec<String> = ["a.txt", "b.txt", "c.txt"]
+            .iter()
+            .map(|name| root.path().join(name).to_string_lossy().to_string())
+            .collect();
+
+        let key = cache_key();
+        let chunking =
+            Chunking::new(&ChunkingConfig::default(), Some(4), None, Some(Sizer::Characters)).unwrap();
+        let mut index = Index::open_in(cache.path(), root_str, &key).unwrap();
+        index
+            .update(&files, &FakeEmbeddingsClient::new(), &chunking, &TextReader::default())
+            .await
+            .unwrap();
+        assert_eq!(index.rows(), 22);
+        let hnsw = index.hnsw(HnswParams::default()).unwrap();
+
+        let options = SearchOptions {
+            paths: &[],
+            walk: &WalkOptions::default(),
+            floor: 0.2,
+            top: Some(3),
+            max_per_file: Some(1),
+            no_query: false,
+            hybrid: None,
+            ann: None,
+            explain: false,
+            chunking: &chunking,
+            format: OutputFormat::Text,
+            should_print: false,
+        };
+        let found: Vec<&str> = ann_top(&index, &hnsw, &[1.0, 0.0, 0.0], 3, &options)
+            .into_sorted_vec()
+            .into_iter()
+            .map(|scored| scored.item.entry.path.as_str())
+            .collect();
+        assert_eq!(found, vec!["a.txt", "b.txt", "c.txt"]);
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{
+    chunker::{Chunk, Chunking},
+    clients::{EmbeddingsClient, EmbeddingsClientImpl},
+    feature::default::{chunk_symbol, open_indexes, report_skipped},
+    files::WalkOptions,
+    index::SkippedFile,
+    lsh::similar_pairs,
+    utils::one_line,
+};
+
+/// Longest the chunks without a symbol are shown as in the text format
+const PREVIEW_CHARS: usize = 60;
+
+pub struct DupesOptions<'a> {
+    /// Chunks at least this similar are duplicates
+    pub threshold: f32,
+    /// Chunks of the same file are only duplicates when there are at least
+    /// this many lines between them
+    pub min_distance: usize,
+    /// Which files under the directories are searched
+    pub walk: &'a WalkOptions,
+    /// How files are split into chunks
+    pub chunking: &'a Chunking,
+    /// Print JSON instead of text
+    pub json: bool,
+}
+
+#[derive(Serialize)]
+struct Duplicate<'a> {
+    file: String,
+    start_line: usize,
+    end_line: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    symbol: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<usize>,
+    chunk: &'a str,
+}
+
+/// Chunks that are duplicates of each other, directly or through other
+/// members of the set
+#[derive(Serialize)]
+struct DuplicateSet<'a> {
+    /// Similarity of the most similar pair in the set
+    similarity: f32,
+    chunks: Vec<Duplicate<'a>>,
+}
+
+#[derive(Serialize)]
+struct Output<'a> {
+    model: &'a str,
+    client: &'a str,
+    sets: Vec<DuplicateSet<'a>>,
+}
+
+/// Whether two chunks of the same file are far enough apart to count as
+/// duplicates rather than neighbours
+fn far_apart(a: &Chunk, b: &Chunk, min_distance: usize) -> bool {
+    let (first, second) = if a.start_line <= b.start_line { (a, b) } else { (b, a) };
+    first.end_line + min_distance < second.start_line
+}
+
+fn root(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+/// Join the pairs into sets of items connected by them, with the similarity
+/// of the most similar pair in each set. The most similar sets come first,
+/// members are in the order of the items.
+fn duplicate_sets(items: usize, pairs: &[(usize, usize, f32)]) -> Vec<(f32, Vec<usize>)> {
+    let mut parents: Vec<usize> = (0..items).collect();
+    for &(i, j, _) in pairs {
+        let (i, j) = (root(&mut parents, i), root(&mut parents, j));
+        parents[i.max(j)] = i.min(j);
+    }
+
+    let mut sets: HashMap<usize, (f32, Vec<usize>)> = HashMap::new();
+    for &(i, j, similarity) in pairs {
+        let set = sets.entry(root(&mut parents, i)).or_insert((f32::MIN, Vec::new()));
+        set.0 = set.0.max(similarity);
+        set.1.extend([i, j]);
+    }
+    let mut sets: Vec<(f32, Vec<usize>)> = sets
+        .into_values()
+        .map(|(similarity, mut members)| {
+            members.sort();
+            members.dedup();
+            (similarity, members)
+        })
+        .collect();
+    sets.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    sets
+}
+
+/// Find chunks that are near duplicates of chunks in other files, or far
+/// away in the same file, and print them in sets, most similar first
+pub async fn run(
+    embeddings_client: &EmbeddingsClientImpl,
+    paths: &[String],
+    options: &DupesOptions<'_>,
+) -> Result<()> {
+    let indexes = open_indexes(embeddings_client, paths, options.walk, options.chunking).await?;
+    let skipped: Vec<&SkippedFile> = indexes.iter().flat_map(|index| index.skipped()).collect();
+    report_skipped(&skipped);
+
+    // Every chunk along with the file it is in
+    let mut chunks: Vec<(usize, &Chunk)> = Vec::new();
+    let mut files = Vec::new();
+    let mut vectors: Vec<&[f32]> = Vec::new();
+    for index in &indexes {
+        for entry in index.searched_files() {
+            for (chunk, embedding) in entry.chunks.iter().zip(index.embeddings(entry)) {
+                chunks.push((files.len(), chunk));
+                vectors.push(embedding);
+            }
+            files.push(index.display_path(entry));
+        }
+    }
+
+    let pairs = similar_pairs(&vectors, options.threshold, |i, j| {
+        let ((file_i, chunk_i), (file_j, chunk_j)) = (chunks[i], chunks[j]);
+        file_i != file_j || far_apart(chunk_i, chunk_j, options.min_distance)
+    });
+    let output = Output {
+        model: embeddings_client.model(),
+        client: embeddings_client.name(),
+        sets: duplicate_sets(chunks.len(), &pairs)
+            .into_iter()
+            .map(|(similarity, members)| DuplicateSet {
+                similarity,
+                chunks: members
+                    .into_iter()
+                    .map(|member| {
+                        let (file, chunk) = chunks[member];
+                        let file = &files[file];
+                        Duplicate {
+                            symbol: chunk_symbol(file, chunk),
+                            file: file.clone(),
+                            start_line: chunk.start_line,
+                            end_line: chunk.end_line,
+                            page: chunk.page,
+                            chunk: &chunk.text,
+                        }
+                    })
+                    .collect(),
+            })
+            .collect(),
+    };
+
+    if options.json {
+        println!("{}", serde_json::to_string(&output)?);
+        return Ok(());
+    }
+    for set in &output.sets {
+        println!("similarity {:.3}, {} chunks", set.similarity, set.chunks.len());
+        for chunk in &set.chunks {
+            let label = match &chunk.symbol {
+                Some(symbol) => symbol.clone(),
+                None => one_line(chunk.chunk, PREVIEW_CHARS),
+            };
+            println!(
+                "  {}:{}-{}: {}",
+                chunk.file, chunk.start_line, chunk.end_line, label
+            );
+        }
+        println!();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duplicate_sets() {
+        let pairs = [(0, 4, 0.91), (2, 3, 0.99), (4, 5, 0.96), (0, 5, 0.9)];
+        assert_eq!(
+            duplicate_sets(6, &pairs),
+            vec![(0.99, vec![2, 3]), (0.96, vec![0, 4, 5])]
+        );
+        assert!(duplicate_sets(3, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_far_apart() {
+        let chunk = |start_line, end_line| Chunk {
+            start_line,
+            start_column: 1,
+            end_line,
+            end_column: 1,
+            start: 0,
+            end: 0,
+            symbol: None,
+            page: None,
+            text: String::new(),
+        };
+        assert!(far_apart(&chunk(1, 10), &chunk(40, 50), 20));
+        assert!(far_apart(&chunk(40, 50), &chunk(1, 10), 20));
+        assert!(!far_apart(&chunk(1, 10), &chunk(25, 30), 20));
+        assert!(!far_apart(&chunk(1, 10), &chunk(5, 30), 0));
+    }
+}
@@ -1,2 +1,6 @@
+pub mod cluster;
 pub mod comparison;
+pub mod config;
 pub mod default;
+pub mod dupes;
+pub mod models;
@@ -0,0 +1,76 @@
+use fastembed::TextEmbedding;
+
+use crate::clients::{
+    fastembed::{is_downloaded, model_name, DEFAULT_FASTEMBED_MODEL},
+    ollama::{OllamaEmbeddingsClient, OLLAMA_MODELS},
+    openai::{DEFAULT_OPENAI_MODEL, OPENAI_MODELS},
+};
+
+fn print_model(name: &str, dimensions: usize, status: Option<&str>, default: bool) {
+    let mut details = format!("{} dimensions", dimensions);
+    if let Some(status) = status {
+        details.push_str(", ");
+        details.push_str(status);
+    }
+    if default {
+        details.push_str(", default");
+    }
+    println!("  - {} ({})", name, details);
+}
+
+fn print_fastembed() {
+    println!("fastembed:");
+    let default_name = TextEmbedding::get_model_info(&DEFAULT_FASTEMBED_MODEL)
+        .map(model_name)
+        .unwrap_or_default();
+    for info in TextEmbedding::list_supported_models() {
+        let name = model_name(&info);
+        let status = if is_downloaded(&info) {
+            "downloaded"
+        } else {
+            "not downloaded"
+        };
+        print_model(&name, info.dim, Some(status), name == default_name);
+    }
+}
+
+async fn print_ollama(ollama_client: Option<&OllamaEmbeddingsClient>) {
+    println!("ollama:");
+    // Only say whether a model is pulled if the server could be asked
+    let local_models = match ollama_client {
+        Some(client) => client.local_models().await.ok(),
+        None => None,
+    };
+    for (i, (name, dimensions)) in OLLAMA_MODELS.iter().enumerate() {
+        let status = local_models.as_ref().map(|models| {
+            if models.iter().any(|model| model == name) {
+                "pulled"
+            } else {
+                "not pulled"
+            }
+        });
+        print_model(name, *dimensions, status, i == 0);
+    }
+}
+
+fn print_openai() {
+    println!("openai:");
+    for (name, dimensions) in OPENAI_MODELS.iter() {
+        print_model(name, *dimensions, None, *name == DEFAULT_OPENAI_MODEL);
+    }
+}
+
+/// Print the models available for each client, or only for the given one
+pub async fn run(client: &Option<String>, ollama_client: Option<&OllamaEmbeddingsClient>) {
+    println!("Available models:");
+    let show = |name: &str| client.as_deref().is_none_or(|client| client == name);
+    if show("fastembed") {
+        print_fastembed();
+    }
+    if show("ollama") {
+        print_ollama(ollama_client).await;
+    }
+    if show("openai") {
+        print_openai();
+    }
+}
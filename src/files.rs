@@ -1,82 +1,492 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use serde::{Deserialize, Serialize};
 use std::fs::{self, File};
+use std::io::Read;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+use std::process::Command;
 
-use ignore::WalkBuilder;
+use ignore::{overrides::OverrideBuilder, types::TypesBuilder, WalkBuilder};
 use memmap2::Mmap;
+use tracing::warn;
 
-fn is_binary_file(file: &str) -> bool {
-    if !file.contains(".") {
+use crate::{extract, index::SkippedFile};
+
+/// Extensions of formats that are never text, so files with them can be
+/// skipped without reading them unless a preprocessor is configured for
+/// them
+const BINARY_EXTENSIONS: [&str; 38] = [
+    "png", "jpg", "jpeg", "gif", "bmp", "ico", "tiff", "webp", "svg", "mp3", "mp4", "webm", "ogg",
+    "flac", "wav", "avi", "mov", "wmv", "mpg", "flv", "swf", "zip", "gz", "tar", "rar", "7z",
+    "bz2", "xz", "doc", "xls", "ppt", "eot", "ttf", "woff", "woff2", "otf", "wasm", "exe",
+];
+
+/// How much of the start of a file is read to tell whether it is text
+const SAMPLE_BYTES: u64 = 8192;
+
+/// Lowercase extension of a file, if it has one
+fn extension(file: &str) -> Option<String> {
+    Path::new(file)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_lowercase)
+}
+
+fn has_binary_extension(file: &str) -> bool {
+    extension(file).is_some_and(|extension| BINARY_EXTENSIONS.contains(&extension.as_str()))
+}
+
+/// Unlike binary data, text has no control characters besides whitespace
+/// and the escapes of coloured logs
+fn is_control(c: char) -> bool {
+    c < ' ' && !matches!(c, '\t' | '\n' | '\r' | '\x0c' | '\x1b')
+}
+
+/// Whether bytes are UTF-16 text in the byte order of the encoding, that
+/// decodes without errors and has no control characters. Truncated is set
+/// if the bytes are only the start of a file.
+fn is_utf16_text(bytes: &[u8], encoding: &'static Encoding, truncated: bool) -> bool {
+    // A sample may end in the middle of a character
+    let bytes = if truncated { &bytes[..bytes.len() & !1] } else { bytes };
+    let (text, _) = encoding.decode_without_bom_handling(bytes);
+    let text = match truncated {
+        true => text.strip_suffix('\u{fffd}').unwrap_or(&text),
+        false => &text,
+    };
+    !text.contains('\u{fffd}') && !text.chars().any(is_control)
+}
+
+/// Encoding of UTF-16 text without a byte order mark, recognised by the
+/// zero high bytes of ASCII characters and by decoding into text
+fn utf16_without_bom(bytes: &[u8], truncated: bool) -> Option<&'static Encoding> {
+    let pairs = bytes.len() / 2;
+    if pairs == 0 {
+        return None;
+    }
+    let zeros = |offset: usize| bytes.iter().skip(offset).step_by(2).filter(|b| **b == 0).count();
+    let (even, odd) = (zeros(0), zeros(1));
+    let encoding = if odd * 2 >= pairs && even * 10 < pairs {
+        UTF_16LE
+    } else if even * 2 >= pairs && odd * 10 < pairs {
+        UTF_16BE
+    } else {
+        return None;
+    };
+    is_utf16_text(bytes, encoding, truncated).then_some(encoding)
+}
+
+/// Guess the encoding of text from its byte order mark or its contents,
+/// None if it looks like binary data. Text that is not UTF-8 or UTF-16 is
+/// in whichever legacy encoding chardetng finds most likely. Truncated is
+/// set if the bytes are only the start of a file.
+fn detect_encoding(bytes: &[u8], truncated: bool) -> Option<&'static Encoding> {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return Some(encoding);
+    }
+    if let Some(encoding) = utf16_without_bom(bytes, truncated) {
+        return Some(encoding);
+    }
+    if bytes.contains(&0) {
+        return None;
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(_) => return Some(UTF_8),
+        // A character cut in half at the end of a sample is still text
+        Err(err) if truncated && err.error_len().is_none() => return Some(UTF_8),
+        Err(_) => {}
+    }
+    if bytes.iter().any(|b| is_control(char::from(*b))) {
+        return None;
+    }
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, !truncated);
+    Some(detector.guess(None, false))
+}
+
+/// Decode the contents of a file into UTF-8, in the given encoding or else
+/// the one detected from them. None if they look like binary data.
+pub fn decode(bytes: &[u8], encoding: Option<&'static Encoding>) -> Option<String> {
+    let encoding = match encoding {
+        Some(encoding) => encoding,
+        None => detect_encoding(bytes, false)?,
+    };
+    let (text, _) = encoding.decode_with_bom_removal(bytes);
+    Some(text.into_owned())
+}
+
+fn is_binary_file(file: &str, encoding: Option<&'static Encoding>) -> bool {
+    if has_binary_extension(file) {
         return true;
     }
 
-    let banned_extensions = vec![
-        "png", "jpg", "jpeg", "gif", "bmp", "ico", "tiff", "webp", "svg", "mp3", "mp4", "webm",
-        "ogg", "flac", "wav", "avi", "mov", "wmv", "mpg", "flv", "swf", "zip", "gz", "tar", "rar",
-        "7z", "bz2", "xz", "pdf", "doc", "docx", "xls", "xlsx", "ppt", "pptx", "eot", "ttf", "woff",
-        "woff2", "otf", "swf", "wasm", "webm", "webp", "mp4", "mp3", "ogg", "flac", "wav", "avi",
-        "mov", "wmv", "mpg", "flv", "swf", "zip", "gz", "tar", "rar", "7z", "bz2", "xz", "pdf",
-        "doc", "docx", "xls", "xlsx", "ppt", "pptx", "eot", "ttf", "woff", "woff2", "otf", "swf",
-        "wasm", "webm", "webp", "mp4", "mp3", "ogg", "flac", "wav", "avi", "mov", "wmv", "mpg",
-        "flv", "swf", "zip", "gz", "tar", "rar", "7z", "bz2", "xz", "pdf", "doc", "docx", "xls",
-        "xlsx", "ppt", "pptx", "eot", "ttf", "woff", "woff2", "otf", "swf", "wasm", "webm", "webp",
-        "mp4", "mp3", "ogg", "flac", "wav", "avi", "mov", "wmv", "mpg", "flv", "swf", "zip", "gz",
-        "tar", "rar", "7z", "bz2", "xz", "pdf", "doc", "docx", "xls", "xlsx", "ppt", "pptx", "eot",
-    ];
-
-    if let Some(extension) = file.split('.').last() {
-        return banned_extensions.contains(&extension);
-    } else {
-        return false;
+    let mut sample = Vec::new();
+    match File::open(file).and_then(|handle| handle.take(SAMPLE_BYTES).read_to_end(&mut sample)) {
+        Ok(_) => {
+            let truncated = sample.len() as u64 == SAMPLE_BYTES;
+            // UTF-16 text is full of NUL bytes, so files that are valid in
+            // the UTF-16 encoding the user gave are text
+            let utf16 = encoding.filter(|encoding| *encoding == UTF_16LE || *encoding == UTF_16BE);
+            if utf16.is_some_and(|encoding| is_utf16_text(&sample, encoding, truncated)) {
+                return false;
+            }
+            detect_encoding(&sample, truncated).is_none()
+        }
+        // Leave it to whoever reads the file to report the error
+        Err(_) => false,
+    }
+}
+
+/// How the text of a document is extracted
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum Extractor {
+    /// The built in extractor for the format with this extension
+    BuiltIn(String),
+    /// A preprocessor command from the config
+    Command(String),
+}
+
+/// Reads the text of files, decoding plain text files and extracting the
+/// text of documents like PDFs
+#[derive(Default, Debug)]
+pub struct TextReader {
+    /// Encoding of plain text files, detected from their contents if not
+    /// set
+    pub encoding: Option<&'static Encoding>,
+    /// Commands by lowercase extension without the dot that print the text
+    /// of the file given as their last argument, like ripgrep's --pre. They
+    /// take precedence over the built in extractors.
+    preprocessors: BTreeMap<String, String>,
+}
+
+impl TextReader {
+    /// Reader with preprocessors for extensions like `doc` or `.DOC`, later
+    /// ones replace earlier ones for the same extension
+    pub fn new(
+        encoding: Option<&'static Encoding>,
+        preprocessors: impl IntoIterator<Item = (String, String)>,
+    ) -> Self {
+        TextReader {
+            encoding,
+            preprocessors: preprocessors
+                .into_iter()
+                .map(|(extension, command)| {
+                    (extension.trim_start_matches('.').to_lowercase(), command)
+                })
+                .collect(),
+        }
+    }
+
+    /// How the text of a file is extracted, None for plain text files
+    pub fn extractor(&self, file: &str) -> Option<Extractor> {
+        let extension = extension(file)?;
+        match self.preprocessors.get(&extension) {
+            Some(command) => Some(Extractor::Command(command.clone())),
+            None if extract::EXTENSIONS.contains(&extension.as_str()) => {
+                Some(Extractor::BuiltIn(extension))
+            }
+            None => None,
+        }
+    }
+
+    /// Whether a file is neither text nor a document text can be
+    /// extracted from
+    pub fn is_binary(&self, file: &str) -> bool {
+        self.extractor(file).is_none() && is_binary_file(file, self.encoding)
+    }
+
+    /// Text of a file, with the pages of documents separated by form feeds
+    pub fn read(&self, file: &str) -> Result<String> {
+        match self.extractor(file) {
+            None => read_file_with_fallback(file, self.encoding),
+            Some(Extractor::BuiltIn(extension)) => extract::extract(&fs::read(file)?, &extension),
+            Some(Extractor::Command(command)) => run_preprocessor(&command, file),
+        }
+    }
+}
+
+/// Run a preprocessor on a file and decode what it prints. The command is
+/// split into words like a shell would, quotes and all, but is not run by
+/// one.
+fn run_preprocessor(command: &str, file: &str) -> Result<String> {
+    let words = shell_words::split(command)
+        .with_context(|| format!("invalid preprocessor command {}", command))?;
+    let (program, args) = words.split_first().context("empty preprocessor command")?;
+    let output = Command::new(program)
+        .args(args)
+        .arg(file)
+        .output()
+        .with_context(|| format!("could not run preprocessor {}", program))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow::anyhow!(
+            "preprocessor {} failed with {}: {}",
+            program,
+            output.status,
+            stderr.lines().next().unwrap_or_default()
+        ));
+    }
+    decode(&output.stdout, None)
+        .ok_or_else(|| anyhow::anyhow!("preprocessor {} printed binary data", program))
+}
+
+fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+fn expand_glob(pattern: &str) -> Vec<String> {
+    match glob::glob(pattern) {
+        Ok(paths) => paths
+            .filter_map(|entry| entry.ok())
+            .filter_map(|path| path.to_str().map(|p| p.to_string()))
+            .collect(),
+        Err(err) => {
+            warn!("Invalid glob pattern {}: {}", pattern, err);
+            Vec::new()
+        }
+    }
+}
+
+/// Returns true if the argument names an existing file or directory, or
+/// is a glob that matches at least one
+pub fn resolves_to_path(path: &str) -> bool {
+    Path::new(path).exists() || (is_glob(path) && !expand_glob(path).is_empty())
+}
+
+/// Returns true if the argument looks like it was meant as a path, as it
+/// has a path separator, a glob character or a file extension
+pub fn looks_like_path(path: &str) -> bool {
+    let has_extension = !path.contains(char::is_whitespace)
+        && Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                !extension.is_empty() && extension.chars().all(|c| c.is_ascii_alphanumeric())
+            });
+    path.contains(['/', '\\']) || is_glob(path) || has_extension
+}
+
+/// Name of the ignore file that is read along with .gitignore, for files
+/// that should be in git but not searched
+pub const IGNORE_FILE: &str = ".csepignore";
+
+/// Which of the files under a directory are searched
+#[derive(Default, Debug)]
+pub struct WalkOptions {
+    /// Only search files that match these globs, relative to the
+    /// directory, or skip them if they start with !. Later globs take
+    /// precedence.
+    pub globs: Vec<String>,
+    /// Only search files of these types, like rust or markdown
+    pub types: Vec<String>,
+    /// Never search files of these types
+    pub types_not: Vec<String>,
+    /// Search hidden files and directories
+    pub hidden: bool,
+    /// Don't respect .gitignore, .ignore and .csepignore files
+    pub no_ignore: bool,
+    pub max_depth: Option<usize>,
+    /// Skip files bigger than this many bytes
+    pub max_filesize: Option<u64>,
+    /// Follow symbolic links
+    pub follow: bool,
+    /// How the files are read, which decides which of them are text
+    pub reader: TextReader,
+}
+
+/// A directory that was searched, or the set of individual files that
+/// were given on the command line, along with the files to search in it.
+/// Files are paths as they will be displayed in results.
+pub struct SearchRoot {
+    pub path: String,
+    pub files: Vec<String>,
+    /// Files and directories under the root that the walk could not read
+    pub skipped: Vec<SkippedFile>,
+}
+
+/// Collect all the files to search from a list of files, directories and
+/// globs. Directories are walked recursively and the files found under
+/// them are returned relative to the directory as it was given, an empty
+/// list searches the current directory. Individual files are grouped
+/// under a root for the current directory and are always searched.
+pub fn get_search_roots(paths: &[String], options: &WalkOptions) -> Result<Vec<SearchRoot>> {
+    if paths.is_empty() {
+        return get_search_roots(&[".".to_string()], options);
+    }
+
+    let mut seen = HashSet::new();
+    let mut roots = Vec::new();
+    let mut loose_files = Vec::new();
+
+    for path in paths {
+        let expanded = if is_glob(path) && !Path::new(path).exists() {
+            expand_glob(path)
+        } else {
+            vec![path.clone()]
+        };
+
+        for path in expanded {
+            if Path::new(&path).is_dir() {
+                let (files, skipped) = get_all_files_in_directory(&path, options)?;
+                let files = files
+                    .into_iter()
+                    .filter(|file| seen.insert(file.clone()))
+                    .collect();
+                roots.push(SearchRoot {
+                    path,
+                    files,
+                    skipped,
+                });
+            } else if Path::new(&path).is_file() {
+                if seen.insert(path.clone()) {
+                    loose_files.push(path);
+                }
+            } else {
+                warn!("No such file or directory: {}", path);
+            }
+        }
+    }
+
+    if !loose_files.is_empty() {
+        roots.push(SearchRoot {
+            path: ".".to_string(),
+            files: loose_files,
+            skipped: Vec::new(),
+        });
+    }
+
+    Ok(roots)
+}
+
+/// Flat list of all the files that [get_search_roots] would search
+#[cfg(test)]
+pub fn get_all_files(paths: &[String], options: &WalkOptions) -> Result<Vec<String>> {
+    Ok(get_search_roots(paths, options)?
+        .into_iter()
+        .flat_map(|root| root.files)
+        .collect())
+}
+
+/// A file or directory that could not be walked, with the path of the
+/// error taken out of its message
+fn walk_error(dir: &str, err: ignore::Error) -> SkippedFile {
+    match err {
+        ignore::Error::WithPath { path, err } => SkippedFile {
+            path: path.to_string_lossy().to_string(),
+            reason: err.to_string(),
+        },
+        ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => {
+            walk_error(dir, *err)
+        }
+        ignore::Error::Loop { ancestor, child } => SkippedFile {
+            path: child.to_string_lossy().to_string(),
+            reason: format!("file system loop, points to its ancestor {}", ancestor.display()),
+        },
+        err => SkippedFile {
+            path: dir.to_string(),
+            reason: err.to_string(),
+        },
     }
 }
 
-pub fn get_all_files_in_directory(dir: &str) -> Vec<String> {
+/// Files under a directory that can be searched, along with the files and
+/// directories under it that could not be read
+pub fn get_all_files_in_directory(
+    dir: &str,
+    options: &WalkOptions,
+) -> Result<(Vec<String>, Vec<SkippedFile>)> {
+    let mut overrides = OverrideBuilder::new(dir);
+    for glob in &options.globs {
+        overrides.add(glob)?;
+    }
+
+    let mut types = TypesBuilder::new();
+    types.add_defaults();
+    for name in &options.types {
+        types.select(name);
+    }
+    for name in &options.types_not {
+        types.negate(name);
+    }
+
+    let mut walker = WalkBuilder::new(dir);
+    walker
+        .overrides(overrides.build()?)
+        .types(types.build()?)
+        .hidden(!options.hidden)
+        .max_depth(options.max_depth)
+        .max_filesize(options.max_filesize)
+        .follow_links(options.follow);
+    if options.no_ignore {
+        walker
+            .ignore(false)
+            .git_ignore(false)
+            .git_global(false)
+            .git_exclude(false)
+            .parents(false);
+    } else {
+        walker.add_custom_ignore_filename(IGNORE_FILE);
+    }
+
     let mut files = Vec::new();
+    let mut skipped = Vec::new();
 
-    for result in WalkBuilder::new(dir).build() {
+    for result in walker.build() {
         match result {
             Ok(entry) => {
                 let path = entry.path();
-                // Check if it's a file
-                if path.is_file() {
+                // Check if it's a file, symbolic links are only followed
+                // with --follow
+                if entry.file_type().is_some_and(|file_type| file_type.is_file()) {
                     if let Some(path_str) = path.to_str() {
-                        if !is_binary_file(path_str) {
+                        if !options.reader.is_binary(path_str) {
+                            // Don't prefix results with ./ when searching
+                            // the current directory by default
+                            let path_str = match dir {
+                                "." => path_str.strip_prefix("./").unwrap_or(path_str),
+                                _ => path_str,
+                            };
                             files.push(path_str.to_string());
                         }
                     }
                 }
             }
-            Err(_err) => {
-                //eprintln!("ERROR: {:?}", err),
-                // Fail silently , TODO: Add logs here that only print in debug mode
-            }
+            Err(err) => skipped.push(walk_error(dir, err)),
         }
     }
 
-    files
+    Ok((files, skipped))
 }
 
-pub fn read_file_with_fallback(file: &str) -> Result<String> {
+/// Read a file as UTF-8 text, decoding it from the given encoding or else
+/// the one detected from its contents
+pub fn read_file_with_fallback(file: &str, encoding: Option<&'static Encoding>) -> Result<String> {
+    let decode = |bytes: &[u8]| {
+        decode(bytes, encoding).ok_or_else(|| anyhow::anyhow!("binary or in an unknown encoding"))
+    };
+
     // Try to memory map the file first
     if let Ok(file_handle) = File::open(file) {
         if let Ok(mmap) = unsafe { Mmap::map(&file_handle) } {
-            if let Ok(text) = std::str::from_utf8(&mmap) {
-                return Ok(text.to_string());
-            }
+            return decode(&mmap);
         }
     }
 
-    // Fallback to standard read_to_string if memory mapping fails
-    fs::read_to_string(file).map_err(|e| e.into())
+    // Fallback to reading it normally if memory mapping fails
+    decode(&fs::read(file)?)
 }
 
 #[cfg(test)]
 mod tests {
+    use encoding_rs::{SHIFT_JIS, WINDOWS_1251, WINDOWS_1252};
+
     use super::*;
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_get_all_files_in_directory() {
-        let files = get_all_files_in_directory("data");
+        let (files, skipped) = get_all_files_in_directory("data", &WalkOptions::default()).unwrap();
         // print the list of files
         for file in &files {
             println!("{}", file);
@@ -85,13 +495,312 @@ mod tests {
         assert_eq!(files.contains(&"data/typescript.txt".to_string()), true);
         assert_eq!(files.contains(&"data/rust.txt".to_string()), true);
         assert_eq!(files.len(), 3);
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn test_get_all_files() {
+        let files = get_all_files(
+            &[
+                "data/subdir".to_string(),
+                "data/*.txt".to_string(),
+                "data/rust.txt".to_string(),
+            ],
+            &WalkOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            files,
+            vec![
+                "data/subdir/more.txt".to_string(),
+                "data/rust.txt".to_string(),
+                "data/typescript.txt".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_search_roots() {
+        let roots = get_search_roots(
+            &["data/subdir".to_string(), "data/rust.txt".to_string()],
+            &WalkOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(roots.len(), 2);
+
+        assert_eq!(roots[0].path, "data/subdir");
+        assert_eq!(roots[0].files, vec!["data/subdir/more.txt".to_string()]);
+
+        assert_eq!(roots[1].path, ".");
+        assert_eq!(roots[1].files, vec!["data/rust.txt".to_string()]);
+    }
+
+    #[test]
+    fn test_globs() {
+        let options = WalkOptions {
+            globs: vec!["*.txt".to_string(), "!subdir/**".to_string()],
+            ..Default::default()
+        };
+        let (files, _) = get_all_files_in_directory("data", &options).unwrap();
+        assert_eq!(files.len(), 2);
+        assert!(!files.contains(&"data/subdir/more.txt".to_string()));
+
+        let options = WalkOptions {
+            globs: vec!["rust.*".to_string()],
+            ..Default::default()
+        };
+        let files = get_all_files(
+            &["data".to_string(), "data/typescript.txt".to_string()],
+            &options,
+        )
+        .unwrap();
+        assert_eq!(files, vec!["data/rust.txt", "data/typescript.txt"]);
+
+        let options = WalkOptions {
+            globs: vec!["[".to_string()],
+            ..Default::default()
+        };
+        assert!(get_all_files_in_directory("data", &options).is_err());
+    }
+
+    #[test]
+    fn test_walk_options() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().to_str().unwrap();
+        fs::create_dir_all(root.path().join("src/nested")).unwrap();
+        fs::create_dir_all(root.path().join(".hidden")).unwrap();
+        fs::write(root.path().join("src/main.rs"), "fn main() {}").unwrap();
+        fs::write(root.path().join("src/nested/deep.rs"), "fn deep() {}").unwrap();
+        fs::write(root.path().join("README.md"), "# Readme").unwrap();
+        fs::write(root.path().join("big.txt"), "big ".repeat(1000)).unwrap();
+        fs::write(root.path().join(".hidden/secret.txt"), "secret").unwrap();
+        fs::write(root.path().join("generated.txt"), "generated").unwrap();
+        fs::write(root.path().join(IGNORE_FILE), "generated.txt\n").unwrap();
+
+        let relative = |options: &WalkOptions| {
+            let mut files: Vec<String> = get_all_files_in_directory(dir, options)
+                .unwrap()
+                .0
+                .iter()
+                .map(|file| file[dir.len() + 1..].to_string())
+                .collect();
+            files.sort();
+            files
+        };
+
+        assert_eq!(
+            relative(&WalkOptions::default()),
+            vec!["README.md", "big.txt", "src/main.rs", "src/nested/deep.rs"]
+        );
+
+        let options = WalkOptions {
+            types: vec!["rust".to_string()],
+            max_depth: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(relative(&options), vec!["src/main.rs"]);
+
+        let options = WalkOptions {
+            types_not: vec!["rust".to_string(), "markdown".to_string()],
+            max_filesize: Some(1000),
+            ..Default::default()
+        };
+        assert!(relative(&options).is_empty());
+
+        let options = WalkOptions {
+            hidden: true,
+            no_ignore: true,
+            globs: vec!["*.txt".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            relative(&options),
+            vec![".hidden/secret.txt", "big.txt", "generated.txt"]
+        );
+
+        let options = WalkOptions {
+            types: vec!["no-such-type".to_string()],
+            ..Default::default()
+        };
+        assert!(get_all_files_in_directory(dir, &options).is_err());
+
+        // What can't be walked is skipped rather than left out silently
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.path(), root.path().join("src/loop")).unwrap();
+            let options = WalkOptions {
+                follow: true,
+                ..Default::default()
+            };
+            let (files, skipped) = get_all_files_in_directory(dir, &options).unwrap();
+            assert_eq!(files.len(), 4);
+            assert_eq!(skipped.len(), 1);
+            assert_eq!(skipped[0].path, format!("{}/src/loop", dir));
+            assert!(skipped[0].reason.contains("loop"));
+        }
+    }
+
+    #[test]
+    fn test_resolves_to_path() {
+        assert!(resolves_to_path("data"));
+        assert!(resolves_to_path("data/*.txt"));
+        assert!(!resolves_to_path("data/*.nothing"));
+        assert!(!resolves_to_path("a sentence to compare with"));
+    }
+
+    #[test]
+    fn test_looks_like_path() {
+        assert!(looks_like_path("src/mian.rs"));
+        assert!(looks_like_path("mian.rs"));
+        assert!(looks_like_path("*.nothing"));
+        assert!(!looks_like_path("a sentence to compare with"));
+        assert!(!looks_like_path("It ends here."));
+        assert!(!looks_like_path("e.g. this"));
     }
 
     #[test]
     fn test_is_binary() {
-        assert_eq!(is_binary_file("file.png"), true);
-        assert_eq!(is_binary_file("file.txt"), false);
-        assert_eq!(is_binary_file("file.rs"), false);
-        assert_eq!(is_binary_file("file"), true);
+        let root = tempfile::tempdir().unwrap();
+        let write = |name: &str, contents: &[u8]| {
+            let path = root.path().join(name);
+            fs::write(&path, contents).unwrap();
+            path.to_string_lossy().to_string()
+        };
+
+        // Files without an extension are text as long as their contents are
+        assert!(!is_binary_file(&write("Makefile", b"build:\n\tcargo build\n"), None));
+        assert!(!is_binary_file(&write("LICENSE", b"MIT License\n"), None));
+        assert!(!is_binary_file(&write("deploy", b"#!/bin/sh\necho deploying\n"), None));
+        assert!(!is_binary_file(&write("notes.txt", "caf\u{e9} \u{2603}".as_bytes()), None));
+        assert!(!is_binary_file(&write("empty", b""), None));
+
+        // Binary formats that are not in the extension list
+        assert!(is_binary_file(&write("app.sqlite", b"SQLite format 3\0\x10\0"), None));
+        assert!(is_binary_file(&write("Main.class", b"\xca\xfe\xba\xbe\0\0\0\x34"), None));
+        assert!(is_binary_file(&write("libcsep.so", b"\x7fELF\x02\x01\x01\0"), None));
+        assert!(is_binary_file(&write("random", b"\x01\x02\x03\x80 garbage"), None));
+
+        // Text in other encodings is not binary
+        assert!(!is_binary_file(&write("latin1.txt", b"caf\xe9\n"), None));
+        assert!(!is_binary_file(&write("utf16.txt", b"h\0i\0\n\0"), None));
+
+        // Little endian numbers have the zero bytes of UTF-16, but not its
+        // characters
+        let numbers: Vec<u8> = (1..100u16).flat_map(|n| n.to_le_bytes()).collect();
+        let numbers = write("numbers.bin", &numbers);
+        assert!(is_binary_file(&numbers, None));
+        // Nor are they text when the files are said to be UTF-16, unlike
+        // files that really are
+        assert!(is_binary_file(&numbers, Some(UTF_16LE)));
+        let utf16: Vec<u8> = "\u{65e5}\u{672c}\u{8a9e}\u{306e}\u{6587}\u{7ae0}\u{3067}\u{3059} ok"
+            .encode_utf16()
+            .flat_map(|unit| unit.to_be_bytes())
+            .collect();
+        let utf16 = write("utf16be.txt", &utf16);
+        assert!(is_binary_file(&utf16, None));
+        assert!(!is_binary_file(&utf16, Some(UTF_16BE)));
+
+        // Known extensions are never read
+        assert!(is_binary_file("missing.png", None));
+        assert!(!is_binary_file("missing.rs", None));
+    }
+
+    #[test]
+    fn test_decode() {
+        let utf16le: Vec<u8> = [0xff, 0xfe]
+            .into_iter()
+            .chain("caf\u{e9}".encode_utf16().flat_map(|unit| unit.to_le_bytes()))
+            .collect();
+        let utf16be: Vec<u8> = "caf\u{e9}"
+            .encode_utf16()
+            .flat_map(|unit| unit.to_be_bytes())
+            .collect();
+
+        assert_eq!(decode(b"\xef\xbb\xbfcaf\xc3\xa9", None).unwrap(), "caf\u{e9}");
+        assert_eq!(decode(&utf16le, None).unwrap(), "caf\u{e9}");
+        assert_eq!(decode(&utf16be, None).unwrap(), "caf\u{e9}");
+        assert_eq!(decode(b"caf\xe9 cr\xe8me", None).unwrap(), "caf\u{e9} cr\u{e8}me");
+        assert!(decode(b"\x7fELF\x02\x01\x01\0", None).is_none());
+
+        // A given encoding is used even if another one was detected
+        let koi8 = Encoding::for_label(b"koi8-r");
+        assert_eq!(
+            decode(b"\xf0\xd2\xc9\xd7\xc5\xd4", koi8).unwrap(),
+            "\u{41f}\u{440}\u{438}\u{432}\u{435}\u{442}"
+        );
+
+        // A multi byte character cut off by the end of a sample
+        let text = "\u{2603}".repeat(10);
+        let cut = &text.as_bytes()[..text.len() - 1];
+        assert_eq!(detect_encoding(cut, true), Some(UTF_8));
+        assert_ne!(detect_encoding(cut, false), Some(UTF_8));
+
+        // Legacy encodings are told apart by chardetng
+        let (russian, _, _) = WINDOWS_1251.encode("\u{41f}\u{440}\u{438}\u{432}\u{435}\u{442}, \u{43a}\u{430}\u{43a} \u{434}\u{435}\u{43b}\u{430}? \u{42d}\u{442}\u{43e} \u{442}\u{435}\u{43a}\u{441}\u{442} \u{43d}\u{430} \u{440}\u{443}\u{441}\u{441}\u{43a}\u{43e}\u{43c} \u{44f}\u{437}\u{44b}\u{43a}\u{435}.");
+        assert_eq!(detect_encoding(&russian, false), Some(WINDOWS_1251));
+        let (japanese, _, _) = SHIFT_JIS.encode("\u{3053}\u{3093}\u{306b}\u{3061}\u{306f}\u{3001}\u{4e16}\u{754c}\u{3002}\u{65e5}\u{672c}\u{8a9e}\u{306e}\u{6587}\u{7ae0}\u{3067}\u{3059}\u{3002}");
+        assert_eq!(detect_encoding(&japanese, false), Some(SHIFT_JIS));
+        assert_eq!(detect_encoding(b"caf\xe9 cr\xe8me br\xfbl\xe9e", false), Some(WINDOWS_1252));
+
+        // UTF-16 that doesn't decode, like a lone surrogate, is not text
+        assert_eq!(detect_encoding(b"h\0i\0\x00\xd8", false), None);
+        // unless it was cut off by the end of a sample
+        assert_eq!(detect_encoding(b"h\0e\0l\0l\0o\0\x3d\xd8", true), Some(UTF_16LE));
+    }
+
+    #[test]
+    fn test_text_reader() {
+        let root = tempfile::tempdir().unwrap();
+        let write = |name: &str, contents: &[u8]| {
+            let path = root.path().join(name);
+            fs::write(&path, contents).unwrap();
+            path.to_string_lossy().to_string()
+        };
+        let spec = write("spec.docx", b"PK\x03\x04\x14\0\0\0\x08\0");
+        let notes = write("notes.txt", b"caf\xe9");
+        let legacy = write("minutes.doc", b"Minutes of the meeting");
+
+        let reader = TextReader::default();
+        assert_eq!(reader.extractor(&spec), Some(Extractor::BuiltIn("docx".to_string())));
+        assert_eq!(reader.extractor(&notes), None);
+        assert!(!reader.is_binary(&spec));
+        assert!(reader.is_binary(&legacy));
+        assert!(reader.read(&spec).is_err());
+        assert_eq!(reader.read(&notes).unwrap(), "caf\u{e9}");
+
+        // Preprocessors take precedence over the built in extractors and
+        // are given the file as their last argument
+        let reader = TextReader::new(
+            None,
+            [
+                (".DOC".to_string(), "cat".to_string()),
+                ("docx".to_string(), "false --quiet".to_string()),
+                ("txt".to_string(), "sed 's/caf/the caf/'".to_string()),
+                ("TXT".to_string(), "sed 's/caf/un caf/'".to_string()),
+            ],
+        );
+        assert_eq!(reader.extractor(&legacy), Some(Extractor::Command("cat".to_string())));
+        assert!(!reader.is_binary(&legacy));
+        assert_eq!(reader.read(&legacy).unwrap(), "Minutes of the meeting");
+        let err = reader.read(&spec).unwrap_err();
+        assert!(err.to_string().starts_with("preprocessor false failed"));
+        // Quoted arguments stay whole, and the last preprocessor given for
+        // an extension wins
+        assert_eq!(reader.read(&notes).unwrap(), "un caf\u{e9}");
+    }
+
+    #[test]
+    fn test_read_file() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("legacy.txt");
+        fs::write(&path, b"Une cr\xe8me br\xfbl\xe9e na\xefve\n").unwrap();
+        let path = path.to_str().unwrap();
+        assert_eq!(
+            read_file_with_fallback(path, None).unwrap(),
+            "Une cr\u{e8}me br\u{fb}l\u{e9}e na\u{ef}ve\n"
+        );
+
+        let missing = root.path().join("missing.txt");
+        assert!(read_file_with_fallback(missing.to_str().unwrap(), None).is_err());
     }
 }
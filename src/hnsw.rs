@@ -0,0 +1,300 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashSet},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{similarity::dot, top_k::Scored, utils::Rng};
+
+pub const DEFAULT_M: usize = 16;
+pub const DEFAULT_EF_CONSTRUCTION: usize = 200;
+pub const DEFAULT_EF: usize = 64;
+/// Indexes with fewer rows than this are scanned exactly even with --ann,
+/// which is quick enough and never misses anything
+pub const MIN_ROWS: usize = 10_000;
+/// The layers of the rows are the same on every build of the same vectors
+const SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// How the graph is built, a graph built differently is built again
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HnswParams {
+    /// Links of every row on the layers above the bottom one, which has
+    /// twice as many
+    pub m: usize,
+    /// Candidates looked at when linking a new row, more make a better
+    /// graph that takes longer to build
+    pub ef_construction: usize,
+}
+
+impl Default for HnswParams {
+    fn default() -> Self {
+        HnswParams {
+            m: DEFAULT_M,
+            ef_construction: DEFAULT_EF_CONSTRUCTION,
+        }
+    }
+}
+
+/// Hierarchical navigable small world graph over the rows of a matrix of
+/// normalized vectors, for finding the rows most similar to a normalized
+/// query without comparing it to all of them. Only the links are stored,
+/// the vectors are those of the index it was built for.
+#[derive(Serialize, Deserialize)]
+pub struct Hnsw {
+    /// Vectors file of the index the graph was built for
+    pub generation: String,
+    pub params: HnswParams,
+    /// Neighbours of every row on each layer it is on, bottom layer first
+    links: Vec<Vec<Vec<u32>>>,
+    /// Row on the top layer that every search starts from
+    entry: Option<u32>,
+}
+
+impl Hnsw {
+    /// Build the graph by linking the rows one at a time to the most
+    /// similar rows linked before them
+    pub fn build(generation: String, vectors: &[f32], dimensions: usize, params: HnswParams) -> Self {
+        let mut hnsw = Hnsw {
+            generation,
+            params,
+            links: Vec::new(),
+            entry: None,
+        };
+        if dimensions == 0 {
+            return hnsw;
+        }
+
+        let m = params.m.max(2);
+        let level_factor = 1.0 / (m as f32).ln();
+        let mut rng = Rng(SEED);
+        let row = |i: u32| &vectors[i as usize * dimensions..(i as usize + 1) * dimensions];
+
+        for node in 0..(vectors.len() / dimensions) as u32 {
+            let level = (-rng.next_f32().ln() * level_factor) as usize;
+            hnsw.links.push(vec![Vec::new(); level + 1]);
+            let Some(entry) = hnsw.entry else {
+                hnsw.entry = Some(node);
+                continue;
+            };
+
+            let query = row(node);
+            let top = hnsw.links[entry as usize].len() - 1;
+            let mut entries = vec![entry];
+            for layer in (level + 1..=top).rev() {
+                entries = vec![hnsw.search_layer(vectors, dimensions, query, &entries, 1, layer)[0].0];
+            }
+            for layer in (0..=level.min(top)).rev() {
+                let found = hnsw.search_layer(
+                    vectors,
+                    dimensions,
+                    query,
+                    &entries,
+                    params.ef_construction.max(m),
+                    layer,
+                );
+                let max_links = if layer == 0 { 2 * m } else { m };
+                let neighbours = select(&found, m, |a, b| dot(row(a), row(b)));
+                for &neighbour in &neighbours {
+                    let links = &mut hnsw.links[neighbour as usize][layer];
+                    links.push(node);
+                    if links.len() > max_links {
+                        let mut scored: Vec<(u32, f32)> = links
+                            .iter()
+                            .map(|&other| (other, dot(row(neighbour), row(other))))
+                            .collect();
+                        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+                        *links = select(&scored, max_links, |a, b| dot(row(a), row(b)));
+                    }
+                }
+                hnsw.links[node as usize][layer] = neighbours;
+                entries = found.iter().map(|&(found, _)| found).collect();
+            }
+            if level > top {
+                hnsw.entry = Some(node);
+            }
+        }
+        hnsw
+    }
+
+    /// Number of rows the graph was built over
+    pub fn rows(&self) -> usize {
+        self.links.len()
+    }
+
+    /// The rows on one layer most similar to the query, at most ef of them,
+    /// found by following links from the entries to ever more similar rows
+    fn search_layer(
+        &self,
+        vectors: &[f32],
+        dimensions: usize,
+        query: &[f32],
+        entries: &[u32],
+        ef: usize,
+        layer: usize,
+    ) -> Vec<(u32, f32)> {
+        let score = |row: u32| {
+            let start = row as usize * dimensions;
+            dot(query, &vectors[start..start + dimensions])
+        };
+        let mut visited: HashSet<u32> = entries.iter().copied().collect();
+        // The best candidate to expand on top, and the worst result on top
+        let mut candidates = BinaryHeap::new();
+        let mut results = BinaryHeap::new();
+        for &entry in entries {
+            let score = score(entry);
+            candidates.push(Reverse(Scored { score, item: entry }));
+            results.push(Scored { score, item: entry });
+            if results.len() > ef {
+                results.pop();
+            }
+        }
+
+        while let Some(Reverse(candidate)) = candidates.pop() {
+            let worst = results.peek().map_or(f32::MIN, |worst| worst.score);
+            if candidate.score < worst && results.len() >= ef {
+                break;
+            }
+            let links = self.links[candidate.item as usize].get(layer);
+            for &neighbour in links.into_iter().flatten() {
+                if !visited.insert(neighbour) {
+                    continue;
+                }
+                let score = score(neighbour);
+                let worst = results.peek().map_or(f32::MIN, |worst| worst.score);
+                if results.len() < ef || score > worst {
+                    candidates.push(Reverse(Scored { score, item: neighbour }));
+                    results.push(Scored { score, item: neighbour });
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+
+        results
+            .into_sorted_vec()
+            .into_iter()
+            .map(|scored| (scored.item, scored.score))
+            .collect()
+    }
+
+    /// The rows most similar to the query, as many as ef and most similar
+    /// first. A larger ef finds the most similar rows more reliably but
+    /// takes longer.
+    pub fn search(&self, vectors: &[f32], dimensions: usize, query: &[f32], ef: usize) -> Vec<(usize, f32)> {
+        let Some(entry) = self.entry else {
+            return Vec::new();
+        };
+        let mut entries = vec![entry];
+        for layer in (1..self.links[entry as usize].len()).rev() {
+            entries = vec![self.search_layer(vectors, dimensions, query, &entries, 1, layer)[0].0];
+        }
+        self.search_layer(vectors, dimensions, query, &entries, ef.max(1), 0)
+            .into_iter()
+            .map(|(row, score)| (row as usize, score))
+            .collect()
+    }
+}
+
+/// Up to m of the candidates, most similar first, to link a row to.
+/// Candidates more similar to one already picked than to the row are
+/// skipped so the links go in different directions, then the best of the
+/// skipped ones fill up whatever room is left.
+fn select(candidates: &[(u32, f32)], m: usize, similarity: impl Fn(u32, u32) -> f32) -> Vec<u32> {
+    let mut selected: Vec<u32> = Vec::new();
+    let mut skipped = Vec::new();
+    for &(candidate, score) in candidates {
+        if selected.len() >= m {
+            break;
+        }
+        if selected.iter().all(|&other| similarity(candidate, other) < score) {
+            selected.push(candidate);
+        } else {
+            skipped.push(candidate);
+        }
+    }
+    let room = m - selected.len();
+    selected.extend(skipped.into_iter().take(room));
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::similarity::normalized;
+
+    /// Share of the k most similar rows by exact search that the graph
+    /// finds too, averaged over the queries
+    fn recall(hnsw: &Hnsw, vectors: &[f32], dimensions: usize, queries: &[Vec<f32>], k: usize, ef: usize) -> f32 {
+        let rows: Vec<&[f32]> = vectors.chunks_exact(dimensions).collect();
+        let mut found = 0;
+        for query in queries {
+            let mut exact: Vec<(usize, f32)> = rows
+                .iter()
+                .enumerate()
+                .map(|(row, vector)| (row, dot(query, vector)))
+                .collect();
+            exact.sort_by(|a, b| b.1.total_cmp(&a.1));
+            let approximate: Vec<usize> = hnsw
+                .search(vectors, dimensions, query, ef.max(k))
+                .into_iter()
+                .take(k)
+                .map(|(row, _)| row)
+                .collect();
+            found += exact[..k]
+                .iter()
+                .filter(|(row, _)| approximate.contains(row))
+                .count();
+        }
+        found as f32 / (queries.len() * k) as f32
+    }
+
+    #[test]
+    fn test_recall() {
+        // Random directions are the hardest case, there are no clusters
+        // for the graph to take shortcuts through
+        let dimensions = 32;
+        let mut rng = Rng(42);
+        let mut point = || normalized(&(0..dimensions).map(|_| rng.next_normal()).collect::<Vec<_>>());
+        let vectors: Vec<f32> = (0..2000).flat_map(|_| point()).collect();
+        let queries: Vec<Vec<f32>> = (0..50).map(|_| point()).collect();
+
+        let params = HnswParams {
+            m: 12,
+            ef_construction: 64,
+        };
+        let hnsw = Hnsw::build("vectors.bin".to_string(), &vectors, dimensions, params);
+        assert_eq!(hnsw.rows(), 2000);
+
+        let low = recall(&hnsw, &vectors, dimensions, &queries, 10, 10);
+        let high = recall(&hnsw, &vectors, dimensions, &queries, 10, 100);
+        println!("recall@10 with ef 10: {:.3}, with ef 100: {:.3}", low, high);
+        assert!(high >= 0.95, "recall {} with ef 100", high);
+        assert!(high >= low);
+
+        // Every row finds itself
+        for row in [0, 1234, 1999] {
+            let query = &vectors[row * dimensions..(row + 1) * dimensions];
+            assert_eq!(hnsw.search(&vectors, dimensions, query, 32)[0].0, row);
+        }
+    }
+
+    #[test]
+    fn test_empty_and_tiny() {
+        let hnsw = Hnsw::build(String::new(), &[], 3, HnswParams::default());
+        assert!(hnsw.search(&[], 3, &[1.0, 0.0, 0.0], 10).is_empty());
+
+        let vectors: Vec<f32> = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.9, 0.1, 0.0]]
+            .iter()
+            .flat_map(|vector| normalized(vector))
+            .collect();
+        let hnsw = Hnsw::build(String::new(), &vectors, 3, HnswParams::default());
+        let found: Vec<usize> = hnsw
+            .search(&vectors, 3, &[1.0, 0.0, 0.0], 10)
+            .into_iter()
+            .map(|(row, _)| row)
+            .collect();
+        assert_eq!(found, vec![0, 2, 1]);
+    }
+}
@@ -0,0 +1,933 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{self, File},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use anyhow::Result;
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::{
+    chunker::{get_chunks_and_embeddings, Chunk, ChunkSettings, Chunking, CHUNKER_VERSION},
+    clients::{probed_dimensions, EmbeddingsClient},
+    files::{Extractor, TextReader},
+    hnsw::{Hnsw, HnswParams},
+    lexical::LexicalIndex,
+    similarity::{normalize, scores},
+};
+
+/// Bump this whenever the on disk layout of the index changes
+const INDEX_VERSION: u32 = 4;
+
+const METADATA_FILE: &str = "meta.bin";
+const LEXICAL_FILE: &str = "lexical.bin";
+const HNSW_FILE: &str = "hnsw.bin";
+/// Lengths of the embeddings of the models whose clients have to embed a
+/// probe text to find them out, kept next to the indexes
+const DIMENSIONS_FILE: &str = "dimensions.bin";
+
+pub fn get_cache_path() -> PathBuf {
+    let tmp_dir = dirs::cache_dir().unwrap();
+    tmp_dir.join("csep").join("embeddings")
+}
+
+/// Everything that affects the embeddings stored in the cache besides the
+/// text of the files themselves. Two runs only share an index if their
+/// keys are equal.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CacheKey {
+    pub client: String,
+    pub model: String,
+    pub dimensions: usize,
+    pub chunker_version: u32,
+}
+
+impl CacheKey {
+    pub async fn new<C: EmbeddingsClient + Sync>(embeddings_client: &C) -> Result<Self> {
+        Self::new_in(&get_cache_path(), embeddings_client).await
+    }
+
+    pub async fn new_in<C: EmbeddingsClient + Sync>(
+        cache_path: &Path,
+        embeddings_client: &C,
+    ) -> Result<Self> {
+        let dimensions = match embeddings_client.dimensions() {
+            Some(dimensions) => dimensions,
+            None => probe_dimensions(cache_path, embeddings_client).await?,
+        };
+        Ok(CacheKey {
+            client: embeddings_client.name().to_string(),
+            model: embeddings_client.model().to_string(),
+            dimensions,
+            chunker_version: CHUNKER_VERSION,
+        })
+    }
+}
+
+/// Length of the embeddings of a model that the client can only find out
+/// by embedding a probe text. It is only probed the first time the model
+/// is used and then remembered in the cache.
+async fn probe_dimensions<C: EmbeddingsClient + Sync>(
+    cache_path: &Path,
+    embeddings_client: &C,
+) -> Result<usize> {
+    let path = cache_path.join(DIMENSIONS_FILE);
+    let mut known: BTreeMap<String, usize> = fs::read(&path)
+        .ok()
+        .and_then(|bytes| bincode::deserialize(&bytes).ok())
+        .unwrap_or_default();
+    let model = format!("{}/{}", embeddings_client.name(), embeddings_client.model());
+    if let Some(&dimensions) = known.get(&model) {
+        return Ok(dimensions);
+    }
+
+    let embeddings = embeddings_client.get_embeddings(&["dimensions"]).await?;
+    let dimensions = probed_dimensions(embeddings_client.model(), &embeddings)?;
+    known.insert(model, dimensions);
+    fs::create_dir_all(cache_path)?;
+    let tmp_file = cache_path.join(format!("{}.tmp", DIMENSIONS_FILE));
+    fs::write(&tmp_file, bincode::serialize(&known)?)?;
+    fs::rename(&tmp_file, &path)?;
+    Ok(dimensions)
+}
+
+/// A file in the index along with what is needed to tell whether it has
+/// changed since it was indexed
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FileEntry {
+    /// Path of the file relative to the root of the index
+    pub path: String,
+    /// Modification time in nanoseconds since the unix epoch
+    pub mtime: u128,
+    pub size: u64,
+    /// SHA-256 of the text of the file
+    pub hash: String,
+    /// Chunk size, overlap and sizer the file was chunked with
+    pub settings: ChunkSettings,
+    /// How the text of the file was extracted, None for plain text
+    pub extractor: Option<Extractor>,
+    pub chunks: Vec<Chunk>,
+    /// Row of the embeddings of the first chunk in the vectors matrix, the
+    /// rows of a file's chunks are contiguous
+    pub first_row: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Metadata {
+    version: u32,
+    key: CacheKey,
+    root: PathBuf,
+    vectors_file: String,
+    rows: usize,
+    files: Vec<FileEntry>,
+}
+
+enum Vectors {
+    Mapped(Mmap),
+    Owned(Vec<f32>),
+}
+
+impl Vectors {
+    fn as_slice(&self) -> &[f32] {
+        match self {
+            Vectors::Mapped(mmap) => bytemuck::cast_slice(mmap),
+            Vectors::Owned(vectors) => vectors,
+        }
+    }
+}
+
+/// A file that was passed to an update but could not be indexed
+#[derive(Serialize, Clone, Debug)]
+pub struct SkippedFile {
+    pub path: String,
+    pub reason: String,
+}
+
+/// What to do with a file when updating the index
+enum Plan {
+    /// The file has not changed, reuse its existing rows
+    Keep(FileEntry),
+    /// The file is new or has changed and needs to be chunked and embedded
+    Embed {
+        path: String,
+        mtime: u128,
+        size: u64,
+        hash: String,
+        text: String,
+        settings: ChunkSettings,
+        extractor: Option<Extractor>,
+    },
+}
+
+/// Persistent embeddings index for a single search root. Embeddings of
+/// all the chunks under the root are normalized and stored as one
+/// contiguous, memory mapped matrix of f32 rows next to a metadata table
+/// describing which rows belong to which file, so searching never has to
+/// touch the source files and scoring a chunk is a single dot product.
+pub struct Index {
+    dir: PathBuf,
+    root: String,
+    canonical_root: PathBuf,
+    key: CacheKey,
+    files: Vec<FileEntry>,
+    vectors: Vectors,
+    vectors_file: Option<String>,
+    /// Paths of the files that were passed to the last update
+    searched: HashSet<String>,
+    /// Files of the last update that could not be read
+    skipped: Vec<SkippedFile>,
+}
+
+impl Index {
+    /// Open the index for a root directory as it was given on the command
+    /// line, an index that does not exist yet or can't be used is empty
+    pub fn open(root: &str, key: &CacheKey) -> Result<Self> {
+        Self::open_in(&get_cache_path(), root, key)
+    }
+
+    pub fn open_in(cache_path: &Path, root: &str, key: &CacheKey) -> Result<Self> {
+        let canonical_root = fs::canonicalize(root)?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(INDEX_VERSION.to_le_bytes());
+        hasher.update(canonical_root.to_string_lossy().as_bytes());
+        hasher.update(bincode::serialize(key)?);
+        let dir = cache_path.join(format!("{:x}", hasher.finalize()));
+
+        let mut index = Index {
+            dir,
+            root: root.to_string(),
+            canonical_root,
+            key: key.clone(),
+            files: Vec::new(),
+            vectors: Vectors::Owned(Vec::new()),
+            vectors_file: None,
+            searched: HashSet::new(),
+            skipped: Vec::new(),
+        };
+
+        if index.dir.join(METADATA_FILE).exists() {
+            if let Err(err) = index.load() {
+                warn!("Error loading index for {}, rebuilding: {}", root, err);
+                index.files = Vec::new();
+                index.vectors = Vectors::Owned(Vec::new());
+                index.vectors_file = None;
+            }
+        }
+
+        Ok(index)
+    }
+
+    fn load(&mut self) -> Result<()> {
+        let metadata: Metadata = bincode::deserialize(&fs::read(self.dir.join(METADATA_FILE))?)?;
+
+        if metadata.version != INDEX_VERSION
+            || metadata.key != self.key
+            || metadata.root != self.canonical_root
+        {
+            return Err(anyhow::anyhow!("index metadata does not match"));
+        }
+
+        let vectors_file = File::open(self.dir.join(&metadata.vectors_file))?;
+        let expected_len = metadata.rows * self.key.dimensions * std::mem::size_of::<f32>();
+        if vectors_file.metadata()?.len() != expected_len as u64 {
+            return Err(anyhow::anyhow!("vectors file has the wrong size"));
+        }
+
+        // Mapping an empty file fails on some platforms
+        self.vectors = if expected_len == 0 {
+            Vectors::Owned(Vec::new())
+        } else {
+            Vectors::Mapped(unsafe { Mmap::map(&vectors_file)? })
+        };
+        self.files = metadata.files;
+        self.vectors_file = Some(metadata.vectors_file);
+
+        Ok(())
+    }
+
+    /// Path of a file relative to the root of the index
+    fn relative_path(&self, file: &str) -> String {
+        match Path::new(file).strip_prefix(&self.root) {
+            Ok(relative) if !relative.as_os_str().is_empty() => {
+                relative.to_string_lossy().to_string()
+            }
+            _ => file.to_string(),
+        }
+    }
+
+    /// Path of an indexed file as it should be displayed in results
+    pub fn display_path(&self, entry: &FileEntry) -> String {
+        self.display(&entry.path)
+    }
+
+    /// Path relative to the root as it should be displayed
+    fn display(&self, path: &str) -> String {
+        if self.root == "." {
+            return path.to_string();
+        }
+        Path::new(&self.root)
+            .join(path)
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// Bring the index up to date with the given files. Files whose size
+    /// and modification time are unchanged are not read at all, files
+    /// whose text is unchanged are not embedded again unless they are now
+    /// chunked differently or their text is extracted differently. Files
+    /// in the index that are not in the list are kept unless they no
+    /// longer exist, as they may only have been filtered out of the walk.
+    pub async fn update<C: EmbeddingsClient + Sync>(
+        &mut self,
+        files: &[String],
+        embeddings_client: &C,
+        chunking: &Chunking,
+        reader: &TextReader,
+    ) -> Result<()> {
+        let existing: HashMap<&str, &FileEntry> = self
+            .files
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry))
+            .collect();
+
+        let mut plans = Vec::new();
+        let mut searched = HashSet::new();
+        let mut skipped = Vec::new();
+        let mut changed = false;
+
+        for file in files {
+            let path = self.relative_path(file);
+            if !searched.insert(path.clone()) {
+                continue;
+            }
+
+            let (mtime, size) = match fs::metadata(file) {
+                Ok(metadata) => {
+                    let mtime = metadata
+                        .modified()
+                        .ok()
+                        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                        .map(|duration| duration.as_nanos())
+                        .unwrap_or_default();
+                    (mtime, metadata.len())
+                }
+                Err(err) => {
+                    skipped.push(SkippedFile {
+                        path: file.clone(),
+                        reason: err.to_string(),
+                    });
+                    continue;
+                }
+            };
+
+            let settings = chunking.for_path(&path);
+            let extractor = reader.extractor(file);
+            let old = existing
+                .get(path.as_str())
+                .filter(|old| old.settings == settings && old.extractor == extractor);
+            if let Some(old) = old {
+                if old.mtime == mtime && old.size == size {
+                    plans.push(Plan::Keep((*old).clone()));
+                    continue;
+                }
+            }
+
+            let text = match reader.read(file) {
+                Ok(text) => text,
+                Err(err) => {
+                    skipped.push(SkippedFile {
+                        path: file.clone(),
+                        reason: err.to_string(),
+                    });
+                    continue;
+                }
+            };
+            let hash = format!("{:x}", Sha256::digest(text.as_bytes()));
+
+            changed = true;
+            match old {
+                Some(old) if old.hash == hash => plans.push(Plan::Keep(FileEntry {
+                    mtime,
+                    size,
+                    ..(*old).clone()
+                })),
+                _ => plans.push(Plan::Embed {
+                    path,
+                    mtime,
+                    size,
+                    hash,
+                    text,
+                    settings,
+                    extractor,
+                }),
+            }
+        }
+
+        for entry in &self.files {
+            if searched.contains(&entry.path) {
+                continue;
+            }
+            if Path::new(&self.display(&entry.path)).exists() {
+                plans.push(Plan::Keep(entry.clone()));
+            } else {
+                changed = true;
+            }
+        }
+
+        self.searched = searched;
+        self.skipped = skipped;
+
+        if !changed {
+            return Ok(());
+        }
+
+        let chunk_futures: Vec<_> = plans
+            .iter()
+            .filter_map(|plan| match plan {
+                Plan::Embed {
+                    path,
+                    text,
+                    settings,
+                    extractor,
+                    ..
+                } => Some(get_chunks_and_embeddings(
+                    path,
+                    text,
+                    embeddings_client,
+                    &self.key,
+                    settings,
+                    extractor.is_some(),
+                )),
+                Plan::Keep(_) => None,
+            })
+            .collect();
+        let mut chunk_results = futures::future::join_all(chunk_futures).await.into_iter();
+
+        let dimensions = self.key.dimensions;
+        let old_vectors = self.vectors.as_slice();
+        let mut files = Vec::new();
+        let mut vectors = Vec::new();
+
+        for plan in plans {
+            let first_row = vectors.len() / dimensions;
+            match plan {
+                Plan::Keep(entry) => {
+                    let start = entry.first_row * dimensions;
+                    let end = start + entry.chunks.len() * dimensions;
+                    vectors.extend_from_slice(&old_vectors[start..end]);
+                    files.push(FileEntry { first_row, ..entry });
+                }
+                Plan::Embed {
+                    path,
+                    mtime,
+                    size,
+                    hash,
+                    settings,
+                    extractor,
+                    ..
+                } => {
+                    let (chunks, embeddings) = match chunk_results.next() {
+                        Some(Ok(result)) => result,
+                        Some(Err(err)) => {
+                            let file = self.display(&path);
+                            self.skipped.push(SkippedFile {
+                                path: file,
+                                reason: err.to_string(),
+                            });
+                            continue;
+                        }
+                        None => continue,
+                    };
+                    for mut embedding in embeddings {
+                        normalize(&mut embedding);
+                        vectors.extend(embedding);
+                    }
+                    files.push(FileEntry {
+                        path,
+                        mtime,
+                        size,
+                        hash,
+                        settings,
+                        extractor,
+                        chunks,
+                        first_row,
+                    });
+                }
+            }
+        }
+
+        self.files = files;
+        self.vectors = Vectors::Owned(vectors);
+        self.save()
+    }
+
+    /// Write the index to disk. The vectors are written to a new file and
+    /// the metadata pointing at it is swapped in atomically, so a search
+    /// running at the same time never sees a half written index.
+    fn save(&mut self) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+
+        let vectors = self.vectors.as_slice();
+        let nanos = std::time::SystemTime::now()
+            .duration_since(UNIX_EPOCH)?
+            .as_nanos();
+        let vectors_file = format!("vectors-{}.bin", nanos);
+        fs::write(self.dir.join(&vectors_file), bytemuck::cast_slice(vectors))?;
+
+        let metadata = Metadata {
+            version: INDEX_VERSION,
+            key: self.key.clone(),
+            root: self.canonical_root.clone(),
+            vectors_file: vectors_file.clone(),
+            rows: vectors.len() / self.key.dimensions,
+            files: self.files.clone(),
+        };
+        let tmp_file = self.dir.join(format!("{}.tmp", METADATA_FILE));
+        fs::write(&tmp_file, bincode::serialize(&metadata)?)?;
+        fs::rename(&tmp_file, self.dir.join(METADATA_FILE))?;
+
+        if let Some(old_vectors_file) = self.vectors_file.replace(vectors_file) {
+            if let Err(err) = fs::remove_file(self.dir.join(&old_vectors_file)) {
+                warn!("Error removing old vectors file {}: {}", old_vectors_file, err);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Files that were part of the last update
+    pub fn searched_files(&self) -> Vec<&FileEntry> {
+        self.files
+            .iter()
+            .filter(|entry| self.searched.contains(&entry.path))
+            .collect()
+    }
+
+    /// Files of the last update that could not be read
+    pub fn skipped(&self) -> &[SkippedFile] {
+        &self.skipped
+    }
+
+    /// Add files under the root that could not be read before they got to
+    /// the index, like those in directories the walk could not enter
+    pub fn add_skipped(&mut self, skipped: impl IntoIterator<Item = SkippedFile>) {
+        self.skipped.extend(skipped);
+    }
+
+    /// Whether each row of the vectors matrix belongs to a file that was
+    /// part of the last update
+    pub fn searched_rows(&self) -> Vec<bool> {
+        let mut rows = vec![false; self.vectors.as_slice().len() / self.key.dimensions];
+        for entry in self.searched_files() {
+            rows[entry.first_row..entry.first_row + entry.chunks.len()].fill(true);
+        }
+        rows
+    }
+
+    /// Keyword index of the chunks, with the same rows as the vectors. It
+    /// is stored next to the vectors and only rebuilt when they change.
+    pub fn lexical(&self) -> Result<LexicalIndex> {
+        let generation = self.vectors_file.clone().unwrap_or_default();
+        let rows = self.vectors.as_slice().len() / self.key.dimensions;
+        let path = self.dir.join(LEXICAL_FILE);
+
+        if !generation.is_empty() {
+            let cached = fs::read(&path)
+                .ok()
+                .and_then(|bytes| bincode::deserialize::<LexicalIndex>(&bytes).ok());
+            let current = |lexical: &LexicalIndex| {
+                lexical.generation == generation && lexical.rows() == rows
+            };
+            if let Some(lexical) = cached.filter(current) {
+                return Ok(lexical);
+            }
+        }
+
+        let chunks = self
+            .files
+            .iter()
+            .flat_map(|entry| entry.chunks.iter().map(|chunk| chunk.text.as_str()));
+        let lexical = LexicalIndex::build(generation, chunks);
+
+        // An index that was never saved has no directory to store it in
+        if !lexical.generation.is_empty() {
+            let tmp_file = self.dir.join(format!("{}.tmp", LEXICAL_FILE));
+            fs::write(&tmp_file, bincode::serialize(&lexical)?)?;
+            fs::rename(&tmp_file, &path)?;
+        }
+
+        Ok(lexical)
+    }
+
+    /// Number of rows in the vectors matrix, one for every chunk of every
+    /// file in the index
+    pub fn rows(&self) -> usize {
+        self.vectors.as_slice().len() / self.key.dimensions
+    }
+
+    /// Cosine similarity of a normalized query to every row of the vectors
+    /// matrix
+    pub fn similarities(&self, query: &[f32]) -> Vec<f32> {
+        scores(query, self.vectors.as_slice(), self.key.dimensions)
+    }
+
+    /// Nearest neighbour graph of the vectors. Like the keyword index it is
+    /// stored next to the vectors and only rebuilt when they change, or
+    /// when it should be built with different parameters.
+    pub fn hnsw(&self, params: HnswParams) -> Result<Hnsw> {
+        let generation = self.vectors_file.clone().unwrap_or_default();
+        let path = self.dir.join(HNSW_FILE);
+
+        if !generation.is_empty() {
+            let cached = fs::read(&path)
+                .ok()
+                .and_then(|bytes| bincode::deserialize::<Hnsw>(&bytes).ok());
+            let current = |hnsw: &Hnsw| {
+                hnsw.generation == generation && hnsw.params == params && hnsw.rows() == self.rows()
+            };
+            if let Some(hnsw) = cached.filter(current) {
+                return Ok(hnsw);
+            }
+        }
+
+        let hnsw = Hnsw::build(generation, self.vectors.as_slice(), self.key.dimensions, params);
+
+        // An index that was never saved has no directory to store it in
+        if !hnsw.generation.is_empty() {
+            let tmp_file = self.dir.join(format!("{}.tmp", HNSW_FILE));
+            fs::write(&tmp_file, bincode::serialize(&hnsw)?)?;
+            fs::rename(&tmp_file, &path)?;
+        }
+
+        Ok(hnsw)
+    }
+
+    /// Chunks of the searched files whose embeddings the graph finds most
+    /// similar to a normalized query, at most ef of them and most similar
+    /// first
+    pub fn search_hnsw(
+        &self,
+        hnsw: &Hnsw,
+        query: &[f32],
+        ef: usize,
+    ) -> Vec<(&FileEntry, &Chunk, f32)> {
+        hnsw.search(self.vectors.as_slice(), self.key.dimensions, query, ef)
+            .into_iter()
+            .filter_map(|(row, similarity)| {
+                // Rows of the files are in order, so the file of a row is
+                // the last one starting at or before it
+                let file = self.files.partition_point(|entry| entry.first_row <= row);
+                let entry = &self.files[file.checked_sub(1)?];
+                let chunk = entry.chunks.get(row - entry.first_row)?;
+                self.searched
+                    .contains(&entry.path)
+                    .then_some((entry, chunk, similarity))
+            })
+            .collect()
+    }
+
+    /// Embeddings of each of the chunks of an indexed file
+    pub fn embeddings<'a>(&'a self, entry: &FileEntry) -> impl Iterator<Item = &'a [f32]> {
+        let dimensions = self.key.dimensions;
+        let start = entry.first_row * dimensions;
+        let end = start + entry.chunks.len() * dimensions;
+        self.vectors.as_slice()[start..end].chunks_exact(dimensions)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::atomic::Ordering;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        chunker::Sizer,
+        clients::fake::FakeEmbeddingsClient,
+        config::{ChunkingConfig, ChunkingOverride},
+    };
+
+    /// Key of the embeddings of the fake client
+    pub fn cache_key() -> CacheKey {
+        CacheKey {
+            client: "fake".to_string(),
+            model: "letters".to_string(),
+            dimensions: 3,
+            chunker_version: CHUNKER_VERSION,
+        }
+    }
+
+    fn chunking() -> Chunking {
+        Chunking::new(&ChunkingConfig::default(), None, None, None).unwrap()
+    }
+
+    /// Index of a root directory in a cache directory, for the fake client
+    fn open(cache: &TempDir, root: &TempDir) -> Index {
+        Index::open_in(cache.path(), root.path().to_str().unwrap(), &cache_key()).unwrap()
+    }
+
+    /// Bring an index up to date with files read the default way
+    async fn update(
+        index: &mut Index,
+        files: &[String],
+        client: &FakeEmbeddingsClient,
+        chunking: &Chunking,
+    ) -> Result<()> {
+        index.update(files, client, chunking, &TextReader::default()).await
+    }
+
+    fn files_in(root: &Path) -> Vec<String> {
+        let mut files: Vec<String> = fs::read_dir(root)
+            .unwrap()
+            .map(|entry| entry.unwrap().path().to_string_lossy().to_string())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[tokio::test]
+    async fn test_update_is_incremental() {
+        let cache = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let root_str = root.path().to_str().unwrap();
+        fs::write(root.path().join("one.txt"), "an apple a day").unwrap();
+        fs::write(root.path().join("two.txt"), "hello world").unwrap();
+
+        let client = FakeEmbeddingsClient::new();
+        let chunking = chunking();
+
+        let mut index = open(&cache, &root);
+        update(&mut index, &files_in(root.path()), &client, &chunking).await.unwrap();
+        assert_eq!(client.embedded.load(Ordering::SeqCst), 2);
+        assert_eq!(index.searched_files().len(), 2);
+
+        // Nothing changed, so nothing is embedded again even after reopening
+        let mut index = open(&cache, &root);
+        assert_eq!(index.files.len(), 2);
+        update(&mut index, &files_in(root.path()), &client, &chunking).await.unwrap();
+        assert_eq!(client.embedded.load(Ordering::SeqCst), 2);
+
+        // Only the changed file is embedded again, the removed one is pruned
+        fs::write(root.path().join("one.txt"), "an orange every evening").unwrap();
+        fs::remove_file(root.path().join("two.txt")).unwrap();
+        update(&mut index, &files_in(root.path()), &client, &chunking).await.unwrap();
+        assert_eq!(client.embedded.load(Ordering::SeqCst), 3);
+
+        let index = open(&cache, &root);
+        assert_eq!(index.files.len(), 1);
+        let entry = &index.files[0];
+        assert_eq!(entry.path, "one.txt");
+        assert_eq!(index.display_path(entry), format!("{}/one.txt", root_str));
+
+        // Stored normalized
+        let embeddings: Vec<&[f32]> = index.embeddings(entry).collect();
+        let length = (30.0f32).sqrt();
+        assert_eq!(embeddings, vec![&[2.0 / length, 5.0 / length, 1.0 / length][..]]);
+    }
+
+    #[tokio::test]
+    async fn test_update_keeps_files_left_out() {
+        let cache = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("one.txt"), "one").unwrap();
+        fs::write(root.path().join("two.txt"), "two").unwrap();
+        let files = files_in(root.path());
+
+        let client = FakeEmbeddingsClient::new();
+        let chunking = chunking();
+
+        let mut index = open(&cache, &root);
+        update(&mut index, &files, &client, &chunking).await.unwrap();
+        update(&mut index, &files[..1], &client, &chunking).await.unwrap();
+
+        assert_eq!(index.files.len(), 2);
+        let searched = index.searched_files();
+        assert_eq!(searched.len(), 1);
+        assert_eq!(searched[0].path, "one.txt");
+
+        // The file left out is still embedded when it is searched again
+        update(&mut index, &files, &client, &chunking).await.unwrap();
+        assert_eq!(client.embedded.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_lexical_index_follows_vectors() {
+        let cache = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("one.txt"), "fn get_cache_path()").unwrap();
+        fs::write(root.path().join("two.txt"), "hello world").unwrap();
+
+        let client = FakeEmbeddingsClient::new();
+        let chunking = chunking();
+
+        let mut index = open(&cache, &root);
+        update(&mut index, &files_in(root.path()), &client, &chunking).await.unwrap();
+        let lexical = index.lexical().unwrap();
+        assert_eq!(lexical.rows(), 2);
+        assert_eq!(Some(&lexical.generation), index.vectors_file.as_ref());
+        assert!(index.dir.join(LEXICAL_FILE).exists());
+
+        fs::write(root.path().join("two.txt"), "hello\n\nworld").unwrap();
+        update(&mut index, &files_in(root.path()), &client, &chunking).await.unwrap();
+        let rebuilt = index.lexical().unwrap();
+        assert_ne!(rebuilt.generation, lexical.generation);
+        assert_eq!(Some(&rebuilt.generation), index.vectors_file.as_ref());
+
+        update(&mut index, &files_in(root.path())[..1], &client, &chunking).await.unwrap();
+        assert_eq!(index.searched_rows(), vec![true, false]);
+    }
+
+    #[tokio::test]
+    async fn test_hnsw_follows_vectors() {
+        let cache = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("one.txt"), "aaaa").unwrap();
+        fs::write(root.path().join("two.txt"), "eeee").unwrap();
+        fs::write(root.path().join("three.txt"), "aaae").unwrap();
+
+        let client = FakeEmbeddingsClient::new();
+        let chunking = chunking();
+        let params = HnswParams::default();
+
+        let mut index = open(&cache, &root);
+        update(&mut index, &files_in(root.path()), &client, &chunking).await.unwrap();
+        let hnsw = index.hnsw(params).unwrap();
+        assert_eq!(hnsw.rows(), 3);
+        assert_eq!(Some(&hnsw.generation), index.vectors_file.as_ref());
+        assert!(index.dir.join(HNSW_FILE).exists());
+
+        let found: Vec<&str> = index
+            .search_hnsw(&hnsw, &[1.0, 0.0, 0.0], 2)
+            .into_iter()
+            .map(|(entry, _, _)| entry.path.as_str())
+            .collect();
+        assert_eq!(found, vec!["one.txt", "three.txt"]);
+
+        // Different parameters build a different graph
+        let other = HnswParams { m: 4, ..params };
+        assert_eq!(index.hnsw(other).unwrap().params, other);
+
+        fs::write(root.path().join("two.txt"), "eeee\n\neeee").unwrap();
+        update(&mut index, &files_in(root.path()), &client, &chunking).await.unwrap();
+        let rebuilt = index.hnsw(params).unwrap();
+        assert_ne!(rebuilt.generation, hnsw.generation);
+        assert_eq!(rebuilt.rows(), index.rows());
+    }
+
+    #[tokio::test]
+    async fn test_index_depends_on_key() {
+        let cache = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let root_str = root.path().to_str().unwrap();
+        fs::write(root.path().join("one.txt"), "one").unwrap();
+
+        let client = FakeEmbeddingsClient::new();
+        let chunking = chunking();
+
+        let mut index = open(&cache, &root);
+        update(&mut index, &files_in(root.path()), &client, &chunking).await.unwrap();
+
+        let other_key = CacheKey {
+            model: "other".to_string(),
+            ..cache_key()
+        };
+        let index = Index::open_in(cache.path(), root_str, &other_key).unwrap();
+        assert!(index.files.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_changed_chunking_embeds_matching_files() {
+        let cache = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("one.md"), "# One").unwrap();
+        fs::write(root.path().join("two.txt"), "two").unwrap();
+
+        let client = FakeEmbeddingsClient::new();
+        let mut index = open(&cache, &root);
+        update(&mut index, &files_in(root.path()), &client, &chunking()).await.unwrap();
+        assert_eq!(client.embedded.load(Ordering::SeqCst), 2);
+
+        // Only the Markdown file is chunked differently now
+        let mut config = ChunkingConfig::default();
+        config.extensions.insert(
+            "md".to_string(),
+            ChunkingOverride {
+                sizer: Some(Sizer::Characters),
+                ..Default::default()
+            },
+        );
+        let chunking = Chunking::new(&config, None, None, None).unwrap();
+        update(&mut index, &files_in(root.path()), &client, &chunking).await.unwrap();
+        assert_eq!(client.embedded.load(Ordering::SeqCst), 3);
+        assert_eq!(index.files[0].settings.sizer, Sizer::Characters);
+
+        update(&mut index, &files_in(root.path()), &client, &chunking).await.unwrap();
+        assert_eq!(client.embedded.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_files_are_skipped() {
+        let cache = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("latin1.txt"), b"caf\xe9").unwrap();
+        fs::write(root.path().join("image.dat"), b"\x89PNG\r\n\x1a\n\0\0").unwrap();
+        let mut files = files_in(root.path());
+        files.push(root.path().join("missing.txt").to_string_lossy().to_string());
+
+        let client = FakeEmbeddingsClient::new();
+        let mut index = open(&cache, &root);
+        update(&mut index, &files, &client, &chunking()).await.unwrap();
+
+        assert_eq!(index.files.len(), 1);
+        assert_eq!(index.files[0].chunks[0].text, "caf\u{e9}");
+        let skipped: Vec<&str> = index
+            .skipped()
+            .iter()
+            .map(|file| Path::new(&file.path).file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(skipped, vec!["image.dat", "missing.txt"]);
+    }
+
+    #[tokio::test]
+    async fn test_files_that_fail_to_embed_are_skipped() {
+        let cache = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let root_str = root.path().to_str().unwrap();
+        fs::write(root.path().join("one.txt"), "one").unwrap();
+
+        // The fake client only returns 3 dimensional embeddings
+        let key = CacheKey {
+            dimensions: 4,
+            ..cache_key()
+        };
+        let mut index = Index::open_in(cache.path(), root_str, &key).unwrap();
+        let client = FakeEmbeddingsClient::new();
+        update(&mut index, &files_in(root.path()), &client, &chunking()).await.unwrap();
+
+        assert!(index.files.is_empty());
+        let skipped = index.skipped();
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].path, format!("{}/one.txt", root_str));
+        assert!(skipped[0].reason.contains("3 dimensional"), "{}", skipped[0].reason);
+    }
+
+    #[tokio::test]
+    async fn test_dimensions_are_probed_once() {
+        let cache = tempfile::tempdir().unwrap();
+        let client = FakeEmbeddingsClient::new();
+
+        let key = CacheKey::new_in(cache.path(), &client).await.unwrap();
+        assert_eq!(key, cache_key());
+        assert_eq!(client.embedded.load(Ordering::SeqCst), 1);
+
+        let key = CacheKey::new_in(cache.path(), &client).await.unwrap();
+        assert_eq!(key.dimensions, 3);
+        assert_eq!(client.embedded.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// BM25 term frequency saturation
+const K1: f32 = 1.2;
+/// BM25 document length normalisation
+const B: f32 = 0.75;
+/// Reciprocal rank fusion constant, dampens the weight of the top ranks
+const RRF_K: f32 = 60.0;
+
+/// How the semantic and lexical scores of a chunk are combined
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fusion {
+    /// Weighted sum of the cosine similarity and the BM25 score scaled to
+    /// the best match, the weight is that of the cosine similarity
+    Weighted(f32),
+    /// Reciprocal rank fusion of the two rankings
+    Rrf,
+}
+
+/// Split text into lowercase terms. Identifiers are kept whole so that
+/// `get_cache_path` matches exactly, and are also split on underscores and
+/// camel case humps so that their parts match too.
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    for word in text
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|word| !word.trim_matches('_').is_empty())
+    {
+        terms.push(word.to_lowercase());
+
+        let mut parts = Vec::new();
+        for part in word.split('_').filter(|part| !part.is_empty()) {
+            let mut start = 0;
+            let mut previous: Option<char> = None;
+            for (i, c) in part.char_indices() {
+                if previous.is_some_and(|previous| previous.is_lowercase() && c.is_uppercase()) {
+                    parts.push(&part[start..i]);
+                    start = i;
+                }
+                previous = Some(c);
+            }
+            parts.push(&part[start..]);
+        }
+        if parts.len() > 1 {
+            terms.extend(parts.iter().map(|part| part.to_lowercase()));
+        }
+    }
+    terms
+}
+
+/// Inverted index of the terms in every chunk of a search root, stored next
+/// to the embeddings. Rows are the same as the rows of the vectors matrix.
+#[derive(Serialize, Deserialize, Default)]
+pub struct LexicalIndex {
+    /// Vectors file of the index this was built for, it is rebuilt when
+    /// that changes
+    pub generation: String,
+    /// Number of terms in each row
+    lengths: Vec<u32>,
+    /// Rows each term appears in along with how often
+    postings: HashMap<String, Vec<(u32, u32)>>,
+}
+
+impl LexicalIndex {
+    pub fn build<'a>(generation: String, rows: impl Iterator<Item = &'a str>) -> Self {
+        let mut index = LexicalIndex {
+            generation,
+            ..Default::default()
+        };
+        for (row, text) in rows.enumerate() {
+            let terms = tokenize(text);
+            index.lengths.push(terms.len() as u32);
+
+            let mut counts: HashMap<String, u32> = HashMap::new();
+            for term in terms {
+                *counts.entry(term).or_default() += 1;
+            }
+            for (term, count) in counts {
+                index
+                    .postings
+                    .entry(term)
+                    .or_default()
+                    .push((row as u32, count));
+            }
+        }
+        index
+    }
+
+    pub fn rows(&self) -> usize {
+        self.lengths.len()
+    }
+}
+
+/// BM25 scores of the rows of several indexes for a query, counting only
+/// the rows marked as searched. Term statistics are shared between the
+/// indexes so scores from different roots can be compared. Rows that match
+/// none of the query terms are left out.
+pub fn bm25(indexes: &[(&LexicalIndex, &[bool])], query: &str) -> Vec<HashMap<usize, f32>> {
+    let mut terms = tokenize(query);
+    terms.sort();
+    terms.dedup();
+
+    let (rows, total_length) = indexes
+        .iter()
+        .flat_map(|(index, searched)| index.lengths.iter().zip(searched.iter()))
+        .filter(|(_, &searched)| searched)
+        .fold((0usize, 0u64), |(rows, total), (length, _)| {
+            (rows + 1, total + *length as u64)
+        });
+    let mut scores = vec![HashMap::new(); indexes.len()];
+    if rows == 0 {
+        return scores;
+    }
+    let average_length = (total_length as f32 / rows as f32).max(1.0);
+
+    for term in &terms {
+        let postings: Vec<(usize, &[(u32, u32)])> = indexes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (index, _))| Some((i, index.postings.get(term)?.as_slice())))
+            .collect();
+        let frequency = postings
+            .iter()
+            .flat_map(|(i, postings)| postings.iter().map(move |(row, _)| (*i, *row)))
+            .filter(|(i, row)| indexes[*i].1[*row as usize])
+            .count();
+        if frequency == 0 {
+            continue;
+        }
+        let idf = (1.0 + ((rows - frequency) as f32 + 0.5) / (frequency as f32 + 0.5)).ln();
+
+        for (i, postings) in postings {
+            let (index, searched) = indexes[i];
+            for &(row, count) in postings {
+                let row = row as usize;
+                if !searched[row] {
+                    continue;
+                }
+                let count = count as f32;
+                let length = index.lengths[row] as f32;
+                let score = idf * count * (K1 + 1.0)
+                    / (count + K1 * (1.0 - B + B * length / average_length));
+                *scores[i].entry(row).or_default() += score;
+            }
+        }
+    }
+    scores
+}
+
+/// Combine the semantic and lexical scores of a list of candidates into a
+/// single score for each, higher is better
+pub fn fuse(fusion: Fusion, candidates: &[(f32, f32)]) -> Vec<f32> {
+    match fusion {
+        Fusion::Weighted(weight) => {
+            let best_lexical = candidates
+                .iter()
+                .map(|(_, lexical)| *lexical)
+                .fold(0.0, f32::max);
+            candidates
+                .iter()
+                .map(|(semantic, lexical)| {
+                    let lexical = if best_lexical > 0.0 {
+                        lexical / best_lexical
+                    } else {
+                        0.0
+                    };
+                    weight * semantic + (1.0 - weight) * lexical
+                })
+                .collect()
+        }
+        Fusion::Rrf => {
+            let semantic_ranks = ranks(candidates.iter().map(|(semantic, _)| *semantic));
+            let lexical_ranks = ranks(candidates.iter().map(|(_, lexical)| *lexical));
+            candidates
+                .iter()
+                .enumerate()
+                .map(|(i, (_, lexical))| {
+                    let mut score = (RRF_K + semantic_ranks[i] as f32).recip();
+                    // Chunks without any of the terms are not in the
+                    // lexical ranking at all
+                    if *lexical > 0.0 {
+                        score += (RRF_K + lexical_ranks[i] as f32).recip();
+                    }
+                    score
+                })
+                .collect()
+        }
+    }
+}
+
+/// Rank of each score when sorted from highest to lowest, starting at 1
+fn ranks(scores: impl Iterator<Item = f32>) -> Vec<usize> {
+    let scores: Vec<f32> = scores.collect();
+    let mut order: Vec<usize> = (0..scores.len()).collect();
+    order.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+
+    let mut ranks = vec![0; scores.len()];
+    for (rank, i) in order.into_iter().enumerate() {
+        ranks[i] = rank + 1;
+    }
+    ranks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("let path = get_cache_path();"),
+            vec!["let", "path", "get_cache_path", "get", "cache", "path"]
+        );
+        assert_eq!(
+            tokenize("EmbeddingsClient E0308"),
+            vec!["embeddingsclient", "embeddings", "client", "e0308"]
+        );
+        assert!(tokenize("  -> __ ").is_empty());
+    }
+
+    #[test]
+    fn test_bm25() {
+        let index = LexicalIndex::build(
+            "vectors".to_string(),
+            [
+                "the cache is cleared",
+                "fn get_cache_path() returns the cache directory",
+                "nothing to see here",
+            ]
+            .into_iter(),
+        );
+        assert_eq!(index.rows(), 3);
+
+        let searched = vec![true; 3];
+        let scores = bm25(&[(&index, &searched)], "get_cache_path");
+        assert_eq!(scores[0].len(), 2);
+        assert!(scores[0][&1] > scores[0][&0]);
+        assert!(!scores[0].contains_key(&2));
+
+        // Rows that are not searched are neither scored nor counted
+        let searched = vec![true, false, true];
+        let scores = bm25(&[(&index, &searched)], "get_cache_path");
+        assert_eq!(scores[0].keys().collect::<Vec<_>>(), vec![&0]);
+    }
+
+    #[test]
+    fn test_bm25_across_indexes() {
+        let first = LexicalIndex::build("a".to_string(), ["error E0308"].into_iter());
+        let second = LexicalIndex::build("b".to_string(), ["mismatched types"].into_iter());
+        let searched = vec![true];
+        let scores = bm25(&[(&first, &searched), (&second, &searched)], "E0308");
+        assert!(scores[0][&0] > 0.0);
+        assert!(scores[1].is_empty());
+    }
+
+    #[test]
+    fn test_fuse_weighted() {
+        let scores = fuse(Fusion::Weighted(0.5), &[(0.8, 0.0), (0.4, 4.0), (0.2, 2.0)]);
+        for (score, expected) in scores.iter().zip([0.4, 0.7, 0.35]) {
+            assert!((score - expected).abs() < 1e-6, "{} != {}", score, expected);
+        }
+
+        let semantic_only = fuse(Fusion::Weighted(1.0), &[(0.8, 0.0), (0.4, 4.0)]);
+        assert_eq!(semantic_only, vec![0.8, 0.4]);
+    }
+
+    #[test]
+    fn test_fuse_rrf() {
+        let scores = fuse(Fusion::Rrf, &[(0.8, 0.0), (0.4, 4.0), (0.2, 2.0)]);
+        assert_eq!(scores[0], 1.0 / 61.0);
+        assert_eq!(scores[1], 1.0 / 62.0 + 1.0 / 61.0);
+        assert_eq!(scores[2], 1.0 / 63.0 + 1.0 / 62.0);
+        assert!(scores[1] > scores[2] && scores[2] > scores[0]);
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+
+use rayon::prelude::*;
+
+use crate::{similarity::dot, utils::Rng};
+
+/// Up to this many vectors every pair is compared, which is exact and
+/// still quick
+const EXACT_LIMIT: usize = 2000;
+/// Random hyperplanes every vector is projected on, the bits of the bucket
+/// keys are drawn from the signs of these projections
+const HYPERPLANES: usize = 64;
+/// Bits in the bucket key of a table, more bits make smaller buckets
+const KEY_BITS: usize = 14;
+/// Chance of finding a pair that is exactly as similar as the threshold,
+/// more similar pairs are found more often
+const RECALL: f64 = 0.99;
+/// Most tables hashed, which limits the recall of low thresholds
+const MAX_TABLES: usize = 64;
+/// Members of a bucket bigger than this are only paired with this many of
+/// its members rather than all of them, so that thousands of copies of the
+/// same boilerplate don't make millions of pairs. Copies still end up in
+/// the same set through the members they are paired with.
+const MAX_BUCKET: usize = 100;
+/// The hyperplanes are the same on every run, so are the results
+const SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+/// Tables needed to find a pair at the threshold with the wanted recall.
+/// Two vectors land on the same side of a random hyperplane with a chance
+/// of one minus their angle over pi.
+fn tables(threshold: f32) -> usize {
+    let angle = (threshold.clamp(-1.0, 1.0) as f64).acos();
+    let same_key = (1.0 - angle / std::f64::consts::PI).powi(KEY_BITS as i32);
+    if same_key >= 1.0 {
+        return 1;
+    }
+    let tables = ((1.0 - RECALL).ln() / (1.0 - same_key).ln()).ceil();
+    (tables as usize).clamp(1, MAX_TABLES)
+}
+
+/// Pairs of vectors that share a bucket in any table, smaller index first,
+/// sorted and without repeats
+fn candidates(vectors: &[&[f32]], threshold: f32) -> Vec<(u32, u32)> {
+    let mut rng = Rng(SEED);
+    let dimensions = vectors.first().map_or(0, |vector| vector.len());
+    let hyperplanes: Vec<Vec<f32>> = (0..HYPERPLANES)
+        .map(|_| (0..dimensions).map(|_| rng.next_normal()).collect())
+        .collect();
+    let signatures: Vec<u64> = vectors
+        .par_iter()
+        .map(|vector| {
+            hyperplanes
+                .iter()
+                .enumerate()
+                .filter(|(_, hyperplane)| dot(hyperplane, vector) >= 0.0)
+                .fold(0u64, |signature, (bit, _)| signature | 1 << bit)
+        })
+        .collect();
+
+    let mut pairs = Vec::new();
+    for _ in 0..tables(threshold) {
+        let mut bits: Vec<usize> = (0..HYPERPLANES).collect();
+        for i in 0..KEY_BITS {
+            let j = i + (rng.next_u64() % (HYPERPLANES - i) as u64) as usize;
+            bits.swap(i, j);
+        }
+        let mut buckets: HashMap<u64, Vec<u32>> = HashMap::new();
+        for (i, signature) in signatures.iter().enumerate() {
+            let key = bits[..KEY_BITS]
+                .iter()
+                .enumerate()
+                .fold(0u64, |key, (k, &bit)| key | (signature >> bit & 1) << k);
+            buckets.entry(key).or_default().push(i as u32);
+        }
+        for bucket in buckets.values() {
+            if bucket.len() <= MAX_BUCKET {
+                for (n, &i) in bucket.iter().enumerate() {
+                    pairs.extend(bucket[n + 1..].iter().map(|&j| (i, j)));
+                }
+                continue;
+            }
+            let step = bucket.len().div_ceil(MAX_BUCKET);
+            for &j in bucket.iter().step_by(step) {
+                pairs.extend(
+                    bucket
+                        .iter()
+                        .filter(|&&i| i != j)
+                        .map(|&i| (i.min(j), i.max(j))),
+                );
+            }
+        }
+    }
+    pairs.par_sort_unstable();
+    pairs.dedup();
+    pairs
+}
+
+/// Pairs of normalized vectors that are at least threshold similar and
+/// that keep accepts, as indexes with the smaller first and their
+/// similarity, sorted by the indexes. Past a few thousand vectors only the
+/// pairs that share a bucket of random hyperplane hashes are compared, so
+/// some pairs near the threshold may be missed.
+pub fn similar_pairs(
+    vectors: &[&[f32]],
+    threshold: f32,
+    keep: impl Fn(usize, usize) -> bool + Sync,
+) -> Vec<(usize, usize, f32)> {
+    let similar = |(i, j): (usize, usize)| {
+        if !keep(i, j) {
+            return None;
+        }
+        let similarity = dot(vectors[i], vectors[j]);
+        (similarity >= threshold).then_some((i, j, similarity))
+    };
+
+    let mut pairs: Vec<(usize, usize, f32)> = if vectors.len() <= EXACT_LIMIT {
+        (0..vectors.len())
+            .into_par_iter()
+            .flat_map_iter(|i| (i + 1..vectors.len()).filter_map(move |j| similar((i, j))))
+            .collect()
+    } else {
+        candidates(vectors, threshold)
+            .into_par_iter()
+            .filter_map(|(i, j)| similar((i as usize, j as usize)))
+            .collect()
+    };
+    pairs.sort_by_key(|&(i, j, _)| (i, j));
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::similarity::normalized;
+
+    fn exact(vectors: &[&[f32]], threshold: f32) -> Vec<(usize, usize)> {
+        let mut pairs = Vec::new();
+        for i in 0..vectors.len() {
+            for j in i + 1..vectors.len() {
+                let similarity = dot(vectors[i], vectors[j]);
+                if similarity >= threshold {
+                    pairs.push((i, j));
+                }
+            }
+        }
+        pairs
+    }
+
+    #[test]
+    fn test_similar_pairs() {
+        // Random vectors with every tenth one a slightly moved copy of the
+        // one before it
+        let mut rng = Rng(7);
+        let mut owned: Vec<Vec<f32>> = Vec::new();
+        for i in 0..EXACT_LIMIT + 500 {
+            let vector = if i % 10 == 9 {
+                owned[i - 1].iter().map(|x| x + 0.1 * rng.next_normal()).collect()
+            } else {
+                (0..32).map(|_| rng.next_normal()).collect()
+            };
+            owned.push(vector);
+        }
+        let owned: Vec<Vec<f32>> = owned.iter().map(|vector| normalized(vector)).collect();
+        let vectors: Vec<&[f32]> = owned.iter().map(Vec::as_slice).collect();
+
+        let expected = exact(&vectors, 0.95);
+        assert!(expected.len() >= 250);
+        let found: Vec<(usize, usize)> = similar_pairs(&vectors, 0.95, |_, _| true)
+            .into_iter()
+            .map(|(i, j, _)| (i, j))
+            .collect();
+        assert_eq!(found, expected);
+
+        // Few enough vectors to compare every pair
+        let small = &vectors[..100];
+        let found: Vec<(usize, usize)> = similar_pairs(small, 0.95, |i, _| i != 8)
+            .into_iter()
+            .map(|(i, j, _)| (i, j))
+            .collect();
+        let expected: Vec<(usize, usize)> =
+            exact(small, 0.95).into_iter().filter(|&(i, _)| i != 8).collect();
+        assert_eq!(found, expected);
+        assert!(!found.contains(&(8, 9)) && found.contains(&(18, 19)));
+    }
+
+    #[test]
+    fn test_big_buckets() {
+        // Thousands of copies of the same chunk all land in one bucket
+        let mut rng = Rng(11);
+        let base: Vec<f32> = (0..32).map(|_| rng.next_normal()).collect();
+        let owned: Vec<Vec<f32>> = (0..EXACT_LIMIT + 1)
+            .map(|_| normalized(&base.iter().map(|x| x + 0.001 * rng.next_normal()).collect::<Vec<_>>()))
+            .collect();
+        let vectors: Vec<&[f32]> = owned.iter().map(Vec::as_slice).collect();
+
+        let pairs = similar_pairs(&vectors, 0.99, |_, _| true);
+        // Far fewer than the two million pairs of all of them
+        assert!(pairs.len() <= tables(0.99) * vectors.len() * MAX_BUCKET);
+        assert!(pairs.len() < vectors.len() * vectors.len() / 4);
+        let mut paired = vec![false; vectors.len()];
+        for (i, j, _) in pairs {
+            paired[i] = true;
+            paired[j] = true;
+        }
+        assert!(paired.iter().all(|&paired| paired));
+    }
+
+    #[test]
+    fn test_tables() {
+        assert!(tables(0.95) < tables(0.9));
+        assert_eq!(tables(1.0), 1);
+        assert_eq!(tables(0.0), MAX_TABLES);
+    }
+}
@@ -1,20 +1,46 @@
-use args::{Args, SubCommands};
+use args::{Args, ConfigAction, FusionMethod, MatrixFormat, OutputFormat, SubCommands};
 use clap::Parser;
-use clients::ollama::OLLAMA_MODELS;
+use clients::ollama::resolve_url;
+use chunker::Chunking;
+use config::Config;
+use feature::{
+    cluster::{ClusterOptions, Input},
+    default::{AnnOptions, SearchOptions},
+    dupes::DupesOptions,
+};
+use files::{TextReader, WalkOptions};
+use hnsw::{HnswParams, DEFAULT_EF, DEFAULT_EF_CONSTRUCTION, DEFAULT_M};
+use lexical::Fusion;
 use clients::{
-    fastembed::FastEmbeddingsClient, ollama::OllamaEmbeddingsClient, EmbeddingsClientImpl,
+    fastembed::FastEmbeddingsClient,
+    http::is_local_url,
+    ollama::OllamaEmbeddingsClient,
+    openai::{self, OpenAiEmbeddingsClient},
+    EmbeddingsClientImpl,
 };
 use spinners::{Spinner, Spinners};
+use std::env;
 use tracing::error;
-use utils::{cosine_similarity, get_stdin};
+use utils::{get_stdin, read_lines};
 
-use crate::chunker::get_cache_path;
+use crate::index::get_cache_path;
 
 mod args;
 mod chunker;
 mod clients;
+mod cluster;
+mod config;
+mod extract;
 mod feature;
 mod files;
+mod hnsw;
+mod index;
+mod lexical;
+mod lsh;
+mod markdown;
+mod similarity;
+mod syntax;
+mod top_k;
 mod utils;
 
 const DEFAULT_FLOOR: f32 = 0.2;
@@ -27,28 +53,163 @@ async fn main() {
 
     let args = Args::parse();
 
-    if args.list_models {
-        println!("Available models:");
-        for model in OLLAMA_MODELS.iter() {
-            println!("  - {}", model);
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Error loading config: {:#}", err);
+            return;
+        }
+    };
+
+    if let Some(SubCommands::Config(config_args)) = &args.subcmd {
+        match config_args.action {
+            ConfigAction::Show => feature::config::run(&args, &config),
+        }
+        return;
+    }
+
+    // The command line takes precedence over the config files
+    let model = args.model.clone().or(config.model.clone());
+
+    // In offline mode the ollama and openai clients may only talk to a
+    // server running on this machine
+    let check_offline = |url: &str| {
+        if args.offline && !is_local_url(url) {
+            Err(anyhow::anyhow!(
+                "{} is not on this machine and --offline was given",
+                url
+            ))
+        } else {
+            Ok(())
+        }
+    };
+
+    let ollama_client = || {
+        let url = resolve_url(&args.ollama_url, env::var("OLLAMA_HOST").ok(), &config.ollama);
+        check_offline(&url)?;
+        OllamaEmbeddingsClient::new(&model, url, &config.ollama)
+    };
+
+    let fastembed_client = || {
+        let model_path = args.model_path.as_ref().or(config.fastembed.model_path.as_ref());
+        match model_path {
+            Some(dir) => FastEmbeddingsClient::from_dir(dir, &config.fastembed),
+            None => FastEmbeddingsClient::new(&model, args.offline),
         }
+    };
+
+    if args.list_models {
+        let ollama_client = ollama_client().ok();
+        feature::models::run(&args.client, ollama_client.as_ref()).await;
         return;
     }
 
-    let floor = args.floor.unwrap_or(DEFAULT_FLOOR);
+    let floor = args.floor.or(config.floor).unwrap_or(DEFAULT_FLOOR);
+    let top = args.top.or(config.top);
+    let hybrid = args.hybrid.then_some(match args.fusion {
+        FusionMethod::Rrf => Fusion::Rrf,
+        FusionMethod::Weighted => Fusion::Weighted(args.semantic_weight.clamp(0.0, 1.0)),
+    });
+    let ann = (args.ann || config.ann.enabled.unwrap_or(false)).then(|| AnnOptions {
+        params: HnswParams {
+            m: args.hnsw_m.or(config.ann.m).unwrap_or(DEFAULT_M),
+            ef_construction: args
+                .ef_construction
+                .or(config.ann.ef_construction)
+                .unwrap_or(DEFAULT_EF_CONSTRUCTION),
+        },
+        ef: args.ef.or(config.ann.ef).unwrap_or(DEFAULT_EF),
+    });
+    let chunking = match Chunking::new(
+        &config.chunking,
+        args.chunk_tokens,
+        args.chunk_overlap,
+        args.chunk_sizer,
+    ) {
+        Ok(chunking) => chunking,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        }
+    };
+    let format = if args.vimgrep {
+        OutputFormat::Vimgrep
+    } else {
+        args.format.or(config.format).unwrap_or(OutputFormat::Text)
+    };
+
+    // Globs given later take precedence, so the command line wins
+    let globs = config
+        .include
+        .iter()
+        .cloned()
+        .chain(config.exclude.iter().map(|glob| format!("!{}", glob)))
+        .chain(args.globs.iter().cloned())
+        .collect();
+    let walk = WalkOptions {
+        globs,
+        types: args.types.clone(),
+        types_not: args.types_not.clone(),
+        hidden: args.hidden,
+        no_ignore: args.no_ignore,
+        max_depth: args.max_depth,
+        max_filesize: args.max_filesize,
+        follow: args.follow,
+        // Preprocessors on the command line win over the config's
+        reader: TextReader::new(
+            args.encoding,
+            config
+                .preprocessors
+                .iter()
+                .map(|(extension, command)| (extension.clone(), command.clone()))
+                .chain(args.preprocessors.iter().cloned()),
+        ),
+    };
 
-    let embeddings_client = match args.client {
+    let embeddings_client = match args.client.clone().or(config.client.clone()) {
         Some(client) => match client.as_str() {
-            "ollama" => {
-                EmbeddingsClientImpl::Ollama(OllamaEmbeddingsClient::new(&args.model))
+            "ollama" => match ollama_client() {
+                Ok(client) => EmbeddingsClientImpl::Ollama(client),
+                Err(err) => {
+                    eprintln!("Error creating ollama client: {}", err);
+                    return;
+                }
+            },
+            "fastembed" => match fastembed_client() {
+                Ok(client) => EmbeddingsClientImpl::FastEmbed(client),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    return;
+                }
+            },
+            "openai" => {
+                let url = openai::resolve_url(
+                    &args.openai_url,
+                    env::var("OPENAI_BASE_URL").ok(),
+                    &config.openai,
+                );
+                let client = check_offline(&url)
+                    .and_then(|_| OpenAiEmbeddingsClient::new(&model, url, &config.openai));
+                match client {
+                    Ok(client) => EmbeddingsClientImpl::OpenAi(client),
+                    Err(err) => {
+                        eprintln!("Error creating openai client: {}", err);
+                        return;
+                    }
+                }
             }
-            "fastembed" => EmbeddingsClientImpl::FastEmbed(FastEmbeddingsClient::new()),
             _ => {
                 error!("Invalid client: {}", client);
                 return;
             }
         },
-        None => EmbeddingsClientImpl::FastEmbed(FastEmbeddingsClient::new()),
+        None => match fastembed_client() {
+            Ok(client) => EmbeddingsClientImpl::FastEmbed(client),
+            Err(err) => {
+                eprintln!("{}", err);
+                return;
+            }
+        },
     };
 
     if let Some(subcmd) = args.subcmd {
@@ -69,15 +230,22 @@ async fn main() {
                 let mut spinner =
                     Spinner::new(Spinners::Dots9, "Building embeddings cache...".into());
 
-                let run_result = feature::default::run(
-                    &embeddings_client,
-                    &"".to_string(),
-                    &floor,
-                    &true,
-                    &args.vimgrep,
-                    &false,
-                )
-                .await;
+                let options = SearchOptions {
+                    paths: &[],
+                    walk: &walk,
+                    floor,
+                    top,
+                    max_per_file: args.max_per_file,
+                    no_query: true,
+                    hybrid,
+                    ann,
+                    explain: false,
+                    chunking: &chunking,
+                    format,
+                    should_print: false,
+                };
+                let run_result =
+                    feature::default::run(&embeddings_client, "", &options).await;
 
                 match run_result {
                     Ok(_) => return,
@@ -85,6 +253,74 @@ async fn main() {
                 }
                 spinner.stop()
             }
+            SubCommands::Compare(compare_args) => {
+                let mut texts = compare_args.texts;
+                if let Some(file) = &compare_args.file {
+                    match read_lines(file) {
+                        Ok(lines) => texts.extend(lines),
+                        Err(err) => {
+                            eprintln!("{:#}", err);
+                            return;
+                        }
+                    }
+                }
+                let format = compare_args.format.unwrap_or(match format {
+                    OutputFormat::Json | OutputFormat::Jsonl => MatrixFormat::Json,
+                    _ => MatrixFormat::Table,
+                });
+                let run_result =
+                    feature::comparison::run_matrix(&texts, format, &embeddings_client).await;
+                if let Err(err) = run_result {
+                    eprintln!("Error while doing comparison: {}", err);
+                }
+            }
+            SubCommands::Cluster(cluster_args) => {
+                let input = match &cluster_args.file {
+                    Some(file) => match read_lines(file) {
+                        Ok(lines) => Input::Lines(lines),
+                        Err(err) => {
+                            eprintln!("{:#}", err);
+                            return;
+                        }
+                    },
+                    None if cluster_args.paths.is_empty() => Input::Lines(
+                        get_stdin()
+                            .lines()
+                            .filter(|line| !line.trim().is_empty())
+                            .map(str::to_string)
+                            .collect(),
+                    ),
+                    None => Input::Paths(&cluster_args.paths),
+                };
+                let options = ClusterOptions {
+                    method: cluster_args.method,
+                    clusters: cluster_args.clusters,
+                    min_similarity: cluster_args.min_similarity,
+                    walk: &walk,
+                    chunking: &chunking,
+                    json: matches!(format, OutputFormat::Json | OutputFormat::Jsonl),
+                };
+                let run_result = feature::cluster::run(&embeddings_client, input, &options).await;
+                if let Err(err) = run_result {
+                    eprintln!("Error while clustering: {}", err);
+                }
+            }
+            SubCommands::Dupes(dupes_args) => {
+                let options = DupesOptions {
+                    threshold: dupes_args.threshold,
+                    min_distance: dupes_args.min_distance,
+                    walk: &walk,
+                    chunking: &chunking,
+                    json: matches!(format, OutputFormat::Json | OutputFormat::Jsonl),
+                };
+                let run_result =
+                    feature::dupes::run(&embeddings_client, &dupes_args.paths, &options).await;
+                if let Err(err) = run_result {
+                    eprintln!("Error while looking for duplicates: {}", err);
+                }
+            }
+            // Shown before any client is created
+            SubCommands::Config(_) => {}
         }
         return;
     }
@@ -95,8 +331,24 @@ async fn main() {
         search_phrase = stdin_text;
     }
 
-    if let Some(comparison) = args.comparison {
-        let run_result = feature::comparison::run(search_phrase, comparison, &args.model).await;
+    // A single extra argument that isn't a path is a comparison string,
+    // anything else is a list of paths to search
+    let comparison = match args.paths.as_slice() {
+        [single] if !files::resolves_to_path(single) => Some(single.clone()),
+        _ => None,
+    };
+
+    if let Some(comparison) = comparison {
+        // A mistyped path would otherwise print a score with no hint of why
+        if files::looks_like_path(&comparison) {
+            eprintln!(
+                "No file or directory matches {:?}, comparing it with the query as text \
+                 instead. Use `csep compare` to compare texts.",
+                comparison
+            );
+        }
+        let run_result =
+            feature::comparison::run(search_phrase, comparison, &embeddings_client).await;
 
         match run_result {
             Ok(_) => return,
@@ -105,15 +357,21 @@ async fn main() {
         return;
     }
 
-    let run_result = feature::default::run(
-        &embeddings_client,
-        &search_phrase,
-        &floor,
-        &args.no_query,
-        &args.vimgrep,
-        &true,
-    )
-    .await;
+    let options = SearchOptions {
+        paths: &args.paths,
+        walk: &walk,
+        floor,
+        top,
+        max_per_file: args.max_per_file,
+        no_query: args.no_query,
+        hybrid,
+        ann,
+        explain: args.explain,
+        chunking: &chunking,
+        format,
+        should_print: true,
+    };
+    let run_result = feature::default::run(&embeddings_client, &search_phrase, &options).await;
 
     match run_result {
         Ok(_) => return,
@@ -0,0 +1,112 @@
+use std::path::Path;
+
+use pulldown_cmark::{Event, Parser, Tag, TagEnd};
+
+use crate::syntax::Node;
+
+/// A heading and where it starts in the text
+struct Heading {
+    start: usize,
+    level: usize,
+    title: String,
+}
+
+/// The headings of a Markdown document, with the text of their titles
+fn headings(text: &str) -> Vec<Heading> {
+    let mut headings = Vec::new();
+    let mut current: Option<Heading> = None;
+    for (event, range) in Parser::new(text).into_offset_iter() {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                current = Some(Heading {
+                    start: range.start,
+                    level: level as usize,
+                    title: String::new(),
+                })
+            }
+            Event::Text(title) | Event::Code(title) => {
+                if let Some(heading) = current.as_mut() {
+                    heading.title.push_str(&title);
+                }
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some(mut heading) = current.take() {
+                    heading.title = heading.title.trim().to_string();
+                    headings.push(heading);
+                }
+            }
+            _ => {}
+        }
+    }
+    headings
+}
+
+/// Turn a list of headings into a tree of sections, each section running
+/// until the next heading of the same or a higher level
+fn sections(headings: &[Heading], end: usize) -> Vec<Node> {
+    let mut nodes = Vec::new();
+    let mut i = 0;
+    while i < headings.len() {
+        let heading = &headings[i];
+        let next = headings[i + 1..]
+            .iter()
+            .position(|other| other.level <= heading.level)
+            .map_or(headings.len(), |offset| i + 1 + offset);
+        let section_end = headings.get(next).map_or(end, |other| other.start);
+
+        nodes.push(Node {
+            range: heading.start..section_end,
+            label: heading.title.clone(),
+            children: sections(&headings[i + 1..next], section_end),
+        });
+        i = next;
+    }
+    nodes
+}
+
+pub fn is_markdown(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            matches!(extension.to_lowercase().as_str(), "md" | "markdown" | "mdx")
+        })
+}
+
+/// The sections of a Markdown file, or None if it is not a Markdown file
+pub fn parse(path: &str, text: &str) -> Option<Vec<Node>> {
+    is_markdown(path).then(|| sections(&headings(text), text.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sections() {
+        let text = "# csep\nIntro\n\n## Installation\n\n### Ollama client option\nPull a model\n\n```sh\n# not a heading\n```\n\nUsage\n-----\n\n# Appendix ##\n";
+        let nodes = parse("README.md", text).unwrap();
+
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[0].label, "csep");
+        assert_eq!(nodes[1].label, "Appendix");
+        assert_eq!(&text[nodes[1].range.clone()], "# Appendix ##\n");
+
+        let children: Vec<&str> = nodes[0].children.iter().map(|n| n.label.as_str()).collect();
+        assert_eq!(children, vec!["Installation", "Usage"]);
+
+        let installation = &nodes[0].children[0];
+        assert_eq!(installation.children[0].label, "Ollama client option");
+        assert!(text[installation.range.clone()].ends_with("```\n\n"));
+        assert!(text[nodes[0].children[1].range.clone()].starts_with("Usage\n---"));
+    }
+
+    #[test]
+    fn test_not_markdown() {
+        assert!(parse("notes.txt", "# Heading").is_none());
+        assert!(parse("README.MD", "").unwrap().is_empty());
+        let nodes = parse("notes.md", "#hashtag\n\n    # indented code\n\n## `csep` *usage*\n").unwrap();
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].label, "csep usage");
+    }
+}
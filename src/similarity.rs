@@ -0,0 +1,97 @@
+use rayon::prelude::*;
+
+/// Products summed in separate accumulators, so that the loop compiles to
+/// SIMD instructions without reordering the additions of any one of them
+const LANES: usize = 16;
+/// Rows scored by one rayon task, enough to be worth handing out
+const ROWS_PER_TASK: usize = 256;
+
+/// Dot product of two vectors, which is their cosine similarity if both
+/// are normalized
+pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+    let len = a.len().min(b.len());
+    let (a, b) = (a[..len].chunks_exact(LANES), b[..len].chunks_exact(LANES));
+    let tail: f32 = a
+        .remainder()
+        .iter()
+        .zip(b.remainder())
+        .map(|(x, y)| x * y)
+        .sum();
+    let mut sums = [0.0f32; LANES];
+    for (a, b) in a.zip(b) {
+        for ((sum, x), y) in sums.iter_mut().zip(a).zip(b) {
+            *sum += x * y;
+        }
+    }
+    sums.iter().sum::<f32>() + tail
+}
+
+/// Scale a vector to a length of one, a vector without length is left as
+/// it is
+pub fn normalize(vector: &mut [f32]) {
+    let norm = dot(vector, vector).sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+/// The vector scaled to a length of one, or as it is if it has no length
+pub fn normalized(vector: &[f32]) -> Vec<f32> {
+    let mut vector = vector.to_vec();
+    normalize(&mut vector);
+    vector
+}
+
+/// Cosine similarity of two vectors that may not be normalized, vectors
+/// that are should be compared with [dot] instead
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let norms = (dot(a, a) * dot(b, b)).sqrt();
+    if norms == 0.0 {
+        return 0.0;
+    }
+    dot(a, b) / norms
+}
+
+/// Dot product of the query with every row of a matrix of vectors, scored
+/// in parallel across the rows
+pub fn scores(query: &[f32], vectors: &[f32], dimensions: usize) -> Vec<f32> {
+    if dimensions == 0 {
+        return Vec::new();
+    }
+    let mut scores = vec![0.0; vectors.len() / dimensions];
+    scores
+        .par_chunks_mut(ROWS_PER_TASK)
+        .zip(vectors.par_chunks(ROWS_PER_TASK * dimensions))
+        .for_each(|(scores, rows)| {
+            for (score, row) in scores.iter_mut().zip(rows.chunks_exact(dimensions)) {
+                *score = dot(query, row);
+            }
+        });
+    scores
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dot_and_scores() {
+        // Long enough for both the lanes and the tail
+        let a: Vec<f32> = (0..37).map(|i| i as f32 / 10.0).collect();
+        let b: Vec<f32> = (0..37).map(|i| 1.0 - i as f32 / 20.0).collect();
+        let expected: f32 = a.iter().zip(&b).map(|(x, y)| x * y).sum();
+        assert!((dot(&a, &b) - expected).abs() < 1e-3);
+
+        assert!((cosine_similarity(&a, &a) - 1.0).abs() < 1e-6);
+        assert_eq!(cosine_similarity(&a, &[0.0; 37]), 0.0);
+        let (a, b) = (normalized(&a), normalized(&b));
+        assert!((dot(&a, &b) - cosine_similarity(&a, &b)).abs() < 1e-6);
+        assert!((dot(&a, &a) - 1.0).abs() < 1e-6);
+
+        let rows: Vec<f32> = (0..1000).flat_map(|i| [i as f32, 1.0, 0.0]).collect();
+        let all = scores(&[0.0, 2.0, 1.0], &rows, 3);
+        assert_eq!(all.len(), 1000);
+        assert!(all.iter().all(|&score| score == 2.0));
+        assert_eq!(scores(&[1.0, 0.0, 0.0], &rows, 3)[999], 999.0);
+    }
+}
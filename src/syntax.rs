@@ -0,0 +1,397 @@
+use std::{ops::Range, path::Path};
+
+use tree_sitter::{Language, Node as SyntaxNode, Parser};
+
+/// How deeply definitions are looked for in a syntax tree, which can be far
+/// deeper than any definition in generated code
+const MAX_DEPTH: usize = 256;
+
+/// Nodes that wrap a definition along with what belongs to it, like its
+/// decorators or the export keyword
+const WRAPPERS: [&str; 3] = ["decorated_definition", "export_statement", "template_declaration"];
+
+/// A definition in a source file, such as a function, an impl block or a
+/// class, along with the definitions nested in it
+#[derive(Debug, PartialEq)]
+pub struct Node {
+    /// Byte range of the definition, including the comments and
+    /// attributes directly above it
+    pub range: Range<usize>,
+    /// Short description of the definition, like `fn get_cache_path`
+    pub label: String,
+    pub children: Vec<Node>,
+}
+
+/// The languages with a tree-sitter grammar
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Grammar {
+    Rust,
+    Python,
+    Go,
+    JavaScript,
+    TypeScript,
+    Tsx,
+    Java,
+    CSharp,
+    C,
+    Cpp,
+}
+
+impl Grammar {
+    fn for_path(path: &str) -> Option<Self> {
+        let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+        let grammar = match extension.as_str() {
+            "rs" => Grammar::Rust,
+            "py" | "pyi" => Grammar::Python,
+            "go" => Grammar::Go,
+            "js" | "jsx" | "mjs" | "cjs" => Grammar::JavaScript,
+            "ts" | "mts" | "cts" => Grammar::TypeScript,
+            "tsx" => Grammar::Tsx,
+            "java" => Grammar::Java,
+            "cs" => Grammar::CSharp,
+            "c" | "h" => Grammar::C,
+            "cc" | "cpp" | "cxx" | "hh" | "hpp" | "hxx" => Grammar::Cpp,
+            _ => return None,
+        };
+        Some(grammar)
+    }
+
+    fn language(self) -> Language {
+        match self {
+            Grammar::Rust => tree_sitter_rust::LANGUAGE.into(),
+            Grammar::Python => tree_sitter_python::LANGUAGE.into(),
+            Grammar::Go => tree_sitter_go::LANGUAGE.into(),
+            Grammar::JavaScript => tree_sitter_javascript::LANGUAGE.into(),
+            Grammar::TypeScript => tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            Grammar::Tsx => tree_sitter_typescript::LANGUAGE_TSX.into(),
+            Grammar::Java => tree_sitter_java::LANGUAGE.into(),
+            Grammar::CSharp => tree_sitter_c_sharp::LANGUAGE.into(),
+            Grammar::C => tree_sitter_c::LANGUAGE.into(),
+            Grammar::Cpp => tree_sitter_cpp::LANGUAGE.into(),
+        }
+    }
+
+    /// Label of a node, None if it is not a definition
+    fn label(self, node: SyntaxNode, text: &str) -> Option<String> {
+        let field = |name: &str| {
+            node.child_by_field_name(name)
+                .map(|child| squash(&text[child.byte_range()]))
+        };
+        let keyword = |keyword: &str| Some(format!("{} {}", keyword, field("name")?));
+        let method = || Some(format!("{}()", field("name")?));
+
+        match (self, node.kind()) {
+            (Grammar::Rust, "function_item" | "function_signature_item") => keyword("fn"),
+            (Grammar::Rust, "struct_item") => keyword("struct"),
+            (Grammar::Rust, "enum_item") => keyword("enum"),
+            (Grammar::Rust, "union_item") => keyword("union"),
+            (Grammar::Rust, "trait_item") => keyword("trait"),
+            (Grammar::Rust, "mod_item") => keyword("mod"),
+            (Grammar::Rust, "macro_definition") => keyword("macro_rules!"),
+            (Grammar::Rust, "impl_item") => match field("trait") {
+                Some(name) => Some(format!("impl {} for {}", name, field("type")?)),
+                None => Some(format!("impl {}", field("type")?)),
+            },
+
+            (Grammar::Python, "function_definition") => keyword("def"),
+            (Grammar::Python, "class_definition") => keyword("class"),
+
+            (Grammar::Go, "function_declaration") => keyword("func"),
+            (Grammar::Go, "method_declaration") => {
+                Some(format!("func {} {}", field("receiver")?, field("name")?))
+            }
+            (Grammar::Go, "type_declaration") => {
+                let spec = node.named_child(0)?.child_by_field_name("name")?;
+                Some(format!("type {}", &text[spec.byte_range()]))
+            }
+
+            (Grammar::JavaScript | Grammar::TypeScript | Grammar::Tsx, kind) => match kind {
+                "function_declaration" | "generator_function_declaration" => keyword("function"),
+                "class_declaration" | "abstract_class_declaration" => keyword("class"),
+                "interface_declaration" => keyword("interface"),
+                "enum_declaration" => keyword("enum"),
+                "internal_module" => keyword("namespace"),
+                "method_definition" => method(),
+                // Only variables holding a function count
+                "lexical_declaration" | "variable_declaration" => {
+                    let declarator = node.named_child(0)?;
+                    let value = declarator.child_by_field_name("value")?;
+                    if !matches!(value.kind(), "arrow_function" | "function_expression") {
+                        return None;
+                    }
+                    let name = declarator.child_by_field_name("name")?;
+                    let declaration = &text[node.child(0)?.byte_range()];
+                    Some(format!("{} {}", declaration, &text[name.byte_range()]))
+                }
+                _ => None,
+            },
+
+            (Grammar::Java | Grammar::CSharp, kind) => match kind {
+                "class_declaration" => keyword("class"),
+                "interface_declaration" => keyword("interface"),
+                "enum_declaration" => keyword("enum"),
+                "record_declaration" => keyword("record"),
+                "struct_declaration" => keyword("struct"),
+                "namespace_declaration" | "file_scoped_namespace_declaration" => {
+                    keyword("namespace")
+                }
+                "method_declaration" | "constructor_declaration" => method(),
+                _ => None,
+            },
+
+            (Grammar::C | Grammar::Cpp, kind) => match kind {
+                "function_definition" => {
+                    // The name is nested in declarators for the pointers
+                    // and parameters of the function
+                    let mut declarator = node.child_by_field_name("declarator")?;
+                    while declarator.kind() != "function_declarator" {
+                        declarator = declarator.child_by_field_name("declarator")?;
+                    }
+                    let name = declarator.child_by_field_name("declarator")?;
+                    Some(format!("{}()", &text[name.byte_range()]))
+                }
+                // Without a body they are only types of something else
+                "struct_specifier" | "union_specifier" | "enum_specifier" | "class_specifier"
+                    if node.child_by_field_name("body").is_some() =>
+                {
+                    keyword(kind.trim_end_matches("_specifier"))
+                }
+                "namespace_definition" => keyword("namespace"),
+                _ => None,
+            },
+
+            _ => None,
+        }
+    }
+}
+
+/// Collapse the whitespace of a definition's name, which may span lines
+fn squash(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Start of a definition along with what wraps it and the comments and
+/// attributes on the lines directly above it
+fn start(node: SyntaxNode, text: &str) -> usize {
+    let mut node = node;
+    while let Some(parent) = node.parent().filter(|parent| WRAPPERS.contains(&parent.kind())) {
+        node = parent;
+    }
+    let mut start = node.start_byte();
+    let mut sibling = node.prev_sibling();
+    while let Some(previous) = sibling {
+        let is_annotation =
+            previous.kind().contains("comment") || previous.kind() == "attribute_item";
+        if !is_annotation || text[previous.end_byte()..start].matches('\n').count() > 1 {
+            break;
+        }
+        start = previous.start_byte();
+        sibling = previous.prev_sibling();
+    }
+    start
+}
+
+fn definitions(
+    grammar: Grammar,
+    node: SyntaxNode,
+    text: &str,
+    depth: usize,
+    nodes: &mut Vec<Node>,
+) {
+    if depth > MAX_DEPTH {
+        return;
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        match grammar.label(child, text) {
+            Some(label) => {
+                let mut children = Vec::new();
+                definitions(grammar, child, text, depth + 1, &mut children);
+                nodes.push(Node {
+                    range: start(child, text)..child.end_byte(),
+                    label,
+                    children,
+                });
+            }
+            None => definitions(grammar, child, text, depth + 1, nodes),
+        }
+    }
+}
+
+/// The tree-sitter grammar of a source file, or None if the language of
+/// the file is not known
+pub fn language(path: &str) -> Option<Language> {
+    Grammar::for_path(path).map(Grammar::language)
+}
+
+/// The definitions in a source file, or None if the language of the file
+/// is not known
+pub fn parse(path: &str, text: &str) -> Option<Vec<Node>> {
+    let grammar = Grammar::for_path(path)?;
+    let mut parser = Parser::new();
+    parser.set_language(&grammar.language()).ok()?;
+    let tree = parser.parse(text, None)?;
+    let mut nodes = Vec::new();
+    definitions(grammar, tree.root_node(), text, 0, &mut nodes);
+    Some(nodes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Labels of the nodes along with the labels of their children
+    fn outline(nodes: &[Node]) -> Vec<(String, Vec<String>)> {
+        nodes
+            .iter()
+            .map(|node| {
+                let children = node.children.iter().map(|child| child.label.clone());
+                (node.label.clone(), children.collect())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_rust() {
+        let text = r#"use std::path::PathBuf;
+
+/// Where the cache lives, "{" is not a brace
+pub fn get_cache_path() -> PathBuf {
+    let brace = '}';
+    dirs::cache_dir().unwrap()
+}
+
+#[derive(Debug)]
+pub struct Chunk<'a> {
+    text: &'a str,
+}
+
+impl<'a> Display for Chunk<'a> where 'a: 'static {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, r"}}")
+    }
+
+    pub(crate) async fn len(&self) -> usize { self.text.len() }
+}
+"#;
+        let nodes = parse("src/chunker.rs", text).unwrap();
+        assert_eq!(
+            outline(&nodes),
+            vec![
+                ("fn get_cache_path".to_string(), vec![]),
+                ("struct Chunk".to_string(), vec![]),
+                (
+                    "impl Display for Chunk<'a>".to_string(),
+                    vec!["fn fmt".to_string(), "fn len".to_string()]
+                ),
+            ]
+        );
+
+        let function = &text[nodes[0].range.clone()];
+        assert!(function.starts_with("/// Where the cache lives"));
+        assert!(function.ends_with("unwrap()\n}"));
+        assert!(text[nodes[1].range.clone()].starts_with("#[derive(Debug)]"));
+        assert!(text[nodes[2].range.clone()].ends_with("self.text.len() }\n}"));
+    }
+
+    #[test]
+    fn test_parse_python() {
+        let text = r#"import os
+
+@dataclass
+class Index:
+    """A class
+
+def not_a_function():
+    """
+
+    def update(self, files):
+        for file in files:
+            pass
+
+    async def save(self):
+        pass
+
+def main():
+    Index().update([])
+"#;
+        let nodes = parse("index.py", text).unwrap();
+        assert_eq!(
+            outline(&nodes),
+            vec![
+                (
+                    "class Index".to_string(),
+                    vec!["def update".to_string(), "def save".to_string()]
+                ),
+                ("def main".to_string(), vec![]),
+            ]
+        );
+        assert!(text[nodes[0].range.clone()].starts_with("@dataclass"));
+        assert!(text[nodes[0].range.clone()].ends_with("        pass"));
+    }
+
+    #[test]
+    fn test_parse_typescript_and_go() {
+        let text = r#"export const handler = async (event: Event) => {
+  return `}`;
+};
+
+export class Index {
+  private files: string[] = [];
+
+  async update(files: string[]): Promise<void> {
+    console.log(files);
+  }
+}
+"#;
+        let nodes = parse("index.ts", text).unwrap();
+        assert_eq!(
+            outline(&nodes),
+            vec![
+                ("const handler".to_string(), vec![]),
+                ("class Index".to_string(), vec!["update()".to_string()]),
+            ]
+        );
+
+        let text = "type Index struct {\n\tfiles []string\n}\n\nfunc (i *Index) Update(files []string) error {\n\treturn nil\n}\n";
+        let nodes = parse("index.go", text).unwrap();
+        assert_eq!(
+            outline(&nodes),
+            vec![
+                ("type Index".to_string(), vec![]),
+                ("func (i *Index) Update".to_string(), vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_java_and_cpp() {
+        let text = "// The index\n@Deprecated\npublic class Index {\n    public Index() {}\n\n    void update(List<String> files) {}\n}\n";
+        let nodes = parse("Index.java", text).unwrap();
+        assert_eq!(
+            outline(&nodes),
+            vec![(
+                "class Index".to_string(),
+                vec!["Index()".to_string(), "update()".to_string()]
+            )]
+        );
+        assert!(text[nodes[0].range.clone()].starts_with("// The index"));
+
+        let text = "namespace csep {\nstruct Chunk { int start; };\nstruct Chunk *first;\n\nstatic const char *Index::name(int i) {\n  return \"}\";\n}\n}\n";
+        let nodes = parse("index.cpp", text).unwrap();
+        assert_eq!(
+            outline(&nodes),
+            vec![(
+                "namespace csep".to_string(),
+                vec!["struct Chunk".to_string(), "Index::name()".to_string()]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_unknown_language() {
+        assert!(parse("notes.txt", "fn main() {}").is_none());
+        assert!(parse("Makefile", "all:").is_none());
+        assert!(language("Main.kt").is_none());
+    }
+}
@@ -0,0 +1,122 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+/// An item along with the score it is ranked by
+pub struct Scored<T> {
+    pub score: f32,
+    pub item: T,
+}
+
+impl<T> PartialEq for Scored<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Scored<T> {}
+
+impl<T> PartialOrd for Scored<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Scored<T> {
+    /// Reversed so that the heap keeps the lowest score on top, ready to
+    /// be evicted when something better comes along
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.score.total_cmp(&self.score)
+    }
+}
+
+/// Keeps the highest scoring items pushed into it, using a bounded heap so
+/// memory stays flat no matter how many items are pushed. Without a limit
+/// every item is kept.
+pub struct TopK<T> {
+    limit: Option<usize>,
+    heap: BinaryHeap<Scored<T>>,
+}
+
+impl<T> TopK<T> {
+    pub fn new(limit: Option<usize>) -> Self {
+        TopK {
+            limit,
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    pub fn push(&mut self, score: f32, item: T) {
+        match self.limit {
+            Some(0) => {}
+            Some(limit) if self.heap.len() >= limit => {
+                if let Some(mut lowest) = self.heap.peek_mut() {
+                    if score > lowest.score {
+                        *lowest = Scored { score, item };
+                    }
+                }
+            }
+            _ => self.heap.push(Scored { score, item }),
+        }
+    }
+
+    /// Move all the items of another TopK into this one
+    pub fn merge(mut self, other: TopK<T>) -> Self {
+        for scored in other.heap {
+            self.push(scored.score, scored.item);
+        }
+        self
+    }
+
+    /// The kept items, highest score first
+    pub fn into_sorted_vec(self) -> Vec<Scored<T>> {
+        // The ordering is reversed, so ascending order is highest first
+        self.heap.into_sorted_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scores<T>(top: TopK<T>) -> Vec<f32> {
+        top.into_sorted_vec().iter().map(|s| s.score).collect()
+    }
+
+    #[test]
+    fn test_keeps_highest_scores() {
+        let mut top = TopK::new(Some(3));
+        for (i, score) in [0.1, 0.9, 0.4, 0.7, 0.2, 0.8].iter().enumerate() {
+            top.push(*score, i);
+        }
+        let items: Vec<usize> = top.into_sorted_vec().into_iter().map(|s| s.item).collect();
+        assert_eq!(items, vec![1, 5, 3]);
+    }
+
+    #[test]
+    fn test_unbounded() {
+        let mut top = TopK::new(None);
+        for score in [0.1, 0.9, 0.4] {
+            top.push(score, ());
+        }
+        assert_eq!(scores(top), vec![0.9, 0.4, 0.1]);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut first = TopK::new(Some(2));
+        let mut second = TopK::new(Some(2));
+        for score in [0.1, 0.5, 0.3] {
+            first.push(score, ());
+        }
+        for score in [0.4, 0.2] {
+            second.push(score, ());
+        }
+        assert_eq!(scores(first.merge(second)), vec![0.5, 0.4]);
+    }
+
+    #[test]
+    fn test_zero_limit() {
+        let mut top = TopK::new(Some(0));
+        top.push(1.0, ());
+        assert!(scores(top).is_empty());
+    }
+}
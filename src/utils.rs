@@ -1,13 +1,43 @@
+use anyhow::{Context, Result};
 use atty::Stream;
-use rayon::prelude::*;
-use std::io::{self, BufRead};
-
-pub fn cosine_similarity(v1: &Vec<f32>, v2: &Vec<f32>) -> f32 {
-    let dot_product = v1.par_iter().zip(v2).map(|(a, b)| a * b).sum::<f32>();
-    let magnitude_v1 = (v1.par_iter().map(|a| a.powi(2)).sum::<f32>()).sqrt();
-    let magnitude_v2 = (v2.par_iter().map(|a| a.powi(2)).sum::<f32>()).sqrt();
-    let magnitude_product = magnitude_v1 * magnitude_v2;
-    dot_product / magnitude_product
+use std::{
+    fs,
+    io::{self, BufRead},
+    path::Path,
+};
+
+/// xorshift64*, random enough for drawing hyperplanes and graph layers, and
+/// the same for the same seed so results are too
+pub struct Rng(pub u64);
+
+impl Rng {
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Uniform in (0, 1]
+    pub fn next_f32(&mut self) -> f32 {
+        ((self.next_u64() >> 40) + 1) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Standard normal, so that hyperplanes point in every direction
+    /// equally often
+    pub fn next_normal(&mut self) -> f32 {
+        let (u, v) = (self.next_f32(), self.next_f32());
+        (-2.0 * u.ln()).sqrt() * (2.0 * std::f32::consts::PI * v).cos()
+    }
+}
+
+/// Text on a single line, cut short after max_chars characters
+pub fn one_line(text: &str, max_chars: usize) -> String {
+    let line = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match line.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}...", &line[..end]),
+        None => line,
+    }
 }
 
 pub fn get_stdin() -> String {
@@ -27,3 +57,17 @@ pub fn get_stdin() -> String {
     lines.join("\n")
 }
 
+
+/// The lines of a file that are not blank, or of stdin if the path is -
+pub fn read_lines(path: &Path) -> Result<Vec<String>> {
+    let text = if path == Path::new("-") {
+        io::read_to_string(io::stdin())?
+    } else {
+        fs::read_to_string(path).with_context(|| format!("Error reading {}", path.display()))?
+    };
+    Ok(text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::to_string)
+        .collect())
+}